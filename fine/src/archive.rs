@@ -0,0 +1,224 @@
+/*!
+Searching inside archives.
+
+When a search is set to look in [archives](crate::Search::archives), the
+`.tar` (plain, gzipped, or zstd-compressed) and `.zip` files encountered
+while walking the tree are read, and their members are checked against
+the search as if they were part of it. Members are reported with paths
+like `dist/release.tar.gz!/lib/libfoo.so`.
+
+The contents of members are never extracted. Criteria that can only be
+checked on disk (git status, extended attributes, ACLs, hard links) never
+match archive members.
+*/
+use std::{
+    convert::TryFrom,
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bstr::ByteSlice;
+use flate2::read::GzDecoder;
+use time::{Date, Month, PrimitiveDateTime, Time, UtcOffset};
+
+use crate::{search::Entry, times::from_unix, types::EType, Finder, FineErr};
+
+/// Separates the path of an archive from the path of a member inside it.
+pub static SEPARATOR: &str = "!/";
+
+/// Split the path of an archive member into the path of the archive and
+/// the path of the member inside it. Returns `None` if `path` isn't the
+/// path of an archive member.
+pub fn split(path: &Path) -> Option<(&Path, &Path)> {
+    let bytes = <[u8]>::from_path(path)?;
+    let n = bytes.find(SEPARATOR)?;
+    let archive = bytes[..n].to_path().ok()?;
+    let member = bytes[n + SEPARATOR.len()..].to_path().ok()?;
+    Some((archive, member))
+}
+
+/// Bits of a Unix mode that hold the file type, and the value that means
+/// "symbolic link".
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// The kinds of archives that can be searched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl Kind {
+    /// Determine what kind of archive `path` is (if any) from its name.
+    fn of(path: &Path) -> Option<Kind> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Kind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Kind::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Kind::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// What we need to know about a member of an archive.
+struct Member {
+    /// Path relative to the root of the archive.
+    path: PathBuf,
+    etype: EType,
+    mtime: SystemTime,
+}
+
+/// Strip any leading `/` or `./` from the path of an archive member.
+fn clean(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir | Component::CurDir))
+        .collect()
+}
+
+fn tar_members<R: Read>(r: R) -> io::Result<Vec<Member>> {
+    use tar::EntryType;
+
+    let mut archive = tar::Archive::new(r);
+    let mut members = Vec::new();
+    for ent in archive.entries()? {
+        let ent = ent?;
+        let header = ent.header();
+        let etype = match header.entry_type() {
+            // A hard link in a tar file is just another name for a file.
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse | EntryType::Link => {
+                EType::File
+            }
+            EntryType::Directory => EType::Dir,
+            EntryType::Symlink => EType::Link,
+            #[cfg(unix)]
+            EntryType::Fifo => EType::Fifo,
+            #[cfg(any(unix, target_os = "wasi"))]
+            EntryType::Block => EType::Block,
+            #[cfg(any(unix, target_os = "wasi"))]
+            EntryType::Char => EType::Char,
+            _ => continue,
+        };
+        let mtime = from_unix(header.mtime().unwrap_or(0) as i64, 0);
+        let path = clean(&ent.path()?);
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        members.push(Member { path, etype, mtime });
+    }
+
+    Ok(members)
+}
+
+/// Zip files store local times (without a timezone), so we assume they're
+/// in the current one.
+fn zip_time(t: zip::DateTime, offset: UtcOffset) -> Option<SystemTime> {
+    let month = Month::try_from(t.month()).ok()?;
+    let date = Date::from_calendar_date(t.year() as i32, month, t.day()).ok()?;
+    let time = Time::from_hms(t.hour(), t.minute(), t.second()).ok()?;
+    Some(PrimitiveDateTime::new(date, time).assume_offset(offset).into())
+}
+
+fn zip_members(file: File) -> io::Result<Vec<Member>> {
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    let mut members = Vec::with_capacity(archive.len());
+    for n in 0..archive.len() {
+        // The "raw" version doesn't bother setting up decompression.
+        let f = archive.by_index_raw(n)?;
+        let etype = if f.is_dir() {
+            EType::Dir
+        } else if f.unix_mode().map(|m| m & S_IFMT == S_IFLNK).unwrap_or(false) {
+            EType::Link
+        } else {
+            EType::File
+        };
+        let mtime = zip_time(f.last_modified(), offset).unwrap_or(UNIX_EPOCH);
+        let path = clean(Path::new(f.name()));
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        members.push(Member { path, etype, mtime });
+    }
+
+    Ok(members)
+}
+
+/// Read the list of members of the archive at `path`.
+fn read_members(path: &Path, kind: Kind) -> io::Result<Vec<Member>> {
+    let file = File::open(path)?;
+    match kind {
+        Kind::Tar => tar_members(BufReader::new(file)),
+        Kind::TarGz => tar_members(GzDecoder::new(BufReader::new(file))),
+        Kind::TarZst => tar_members(zstd::Decoder::new(file)?),
+        Kind::Zip => zip_members(file),
+    }
+}
+
+impl Finder {
+    /**
+    If `path` (found `depth` levels below the base directory) is an
+    archive, check its members against the search, returning the ones
+    that match (and any error reading it). Returns `None` if `path`
+    isn't an archive.
+    */
+    pub(crate) fn search_archive(
+        &self,
+        path: &Path,
+        depth: usize,
+    ) -> Option<Vec<Result<Entry, FineErr>>> {
+        let kind = Kind::of(path)?;
+        if self.disk_only() {
+            return Some(Vec::new());
+        }
+        let members = match read_members(path, kind) {
+            Ok(members) => members,
+            Err(e) => return Some(vec![Err(FineErr::from_io(path, e))]),
+        };
+
+        let mut matches = Vec::new();
+        let mut pruned: Vec<PathBuf> = Vec::new();
+        for m in members.into_iter() {
+            if pruned.iter().any(|p| m.path.starts_with(p)) {
+                continue;
+            }
+            let depth = depth + m.path.components().count();
+            if let Some(max) = self.max_depth() {
+                if depth > max {
+                    continue;
+                }
+            }
+
+            let mut full: OsString = path.as_os_str().to_owned();
+            full.push(SEPARATOR);
+            full.push(m.path.as_os_str());
+            let full = PathBuf::from(full);
+
+            match self.filter(&full, &m.etype, || Ok(m.mtime)) {
+                Ok(true) => {
+                    if self.prunes() && m.etype == EType::Dir {
+                        pruned.push(m.path);
+                    }
+                    matches.push(Ok(Entry::archived(full, m.etype, depth)));
+                }
+                Ok(false) => {}
+                Err(e) => matches.push(Err(e)),
+            }
+        }
+
+        Some(matches)
+    }
+}
@@ -0,0 +1,228 @@
+/*!
+Persistent defaults and named searches, read from configuration files.
+
+Two files are read, if they exist: the user's `softies/fine.toml` (in
+`$XDG_CONFIG_HOME`, or `~/.config` if that isn't set), and then a
+project-local `.fine.toml` in the current directory or the nearest of its
+ancestors that has one. Values in the project file take precedence.
+
+```toml
+[defaults]
+errors = true
+type = ["file", "link"]
+
+[searches.stale-logs]
+base = "/var/log"
+pattern = ["*.log", "*.log.*"]
+mod-before = "2024-01-01"
+```
+
+Keys are the long names of command-line options (plus `pattern` for the
+patterns themselves); `fine @stale-logs` runs the search with the stored
+options on top of the defaults. An option given on the command line
+replaces any value for it from a configuration file; `--no-config` leaves
+the files out altogether (which is the only way to turn off a flag that
+one of them turns on).
+*/
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+use toml::{Table, Value};
+
+/// Directory (inside the user's config directory) and name of the user's
+/// configuration file.
+static USER_DIR: &str = "softies";
+static USER_FILE: &str = "fine.toml";
+/// Name of the project-local configuration file.
+static LOCAL_FILE: &str = ".fine.toml";
+
+/// Options from a configuration file, as the command-line arguments they
+/// stand in for, keyed by the `clap` id of the option.
+type Args = Vec<(String, Vec<OsString>)>;
+
+/// Add `new` options to `args`, replacing any already set.
+fn merge_args(args: &mut Args, new: Args) {
+    for (id, vals) in new.into_iter() {
+        args.retain(|(other, _)| other != &id);
+        args.push((id, vals));
+    }
+}
+
+/// Defaults and named searches from the configuration files.
+#[derive(Debug, Default)]
+pub struct Config {
+    defaults: Args,
+    searches: HashMap<String, Args>,
+}
+
+/**
+The directory where the softies keep files of some sort: `$VAR/softies`
+if the XDG environment variable `VAR` is set, or `~/DEFAULT/softies`
+otherwise.
+*/
+pub fn user_dir(var: &str, default: &str) -> Option<PathBuf> {
+    let dir = match std::env::var_os(var) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(default),
+    };
+    Some(dir.join(USER_DIR))
+}
+
+fn user_path() -> Option<PathBuf> {
+    Some(user_dir("XDG_CONFIG_HOME", ".config")?.join(USER_FILE))
+}
+
+fn local_path() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(LOCAL_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Turn a single TOML value into a command-line argument value.
+fn value_str(val: &Value) -> Option<String> {
+    match val {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(n) => Some(n.to_string()),
+        Value::Float(x) => Some(x.to_string()),
+        Value::Datetime(t) => Some(t.to_string()),
+        _ => None,
+    }
+}
+
+/// Translate a table of options into the equivalent command-line arguments.
+fn table_args(table: &Table, cmd: &Command, file: &Path) -> Result<Args, String> {
+    let mut args = Args::new();
+
+    for (key, val) in table.iter() {
+        let arg = cmd
+            .get_arguments()
+            .filter(|a| !matches!(a.get_id().as_str(), "help" | "version"))
+            .find(|a| a.get_long() == Some(key.as_str()) || (a.is_positional() && a.get_id() == key))
+            .ok_or_else(|| format!("{}: unknown option \"{}\"", file.display(), key))?;
+
+        let vals: Vec<&Value> = match val {
+            Value::Array(a) => a.iter().collect(),
+            val => vec![val],
+        };
+
+        let mut argv: Vec<OsString> = Vec::new();
+        for val in vals.into_iter() {
+            if !arg.get_action().takes_values() {
+                match val {
+                    Value::Boolean(true) => argv.push(format!("--{}", key).into()),
+                    Value::Boolean(false) => {}
+                    _ => {
+                        return Err(format!(
+                            "{}: option \"{}\" must be true or false",
+                            file.display(),
+                            key
+                        ))
+                    }
+                }
+                continue;
+            }
+
+            let s = value_str(val).ok_or_else(|| {
+                format!("{}: invalid value for option \"{}\"", file.display(), key)
+            })?;
+            if arg.is_positional() {
+                argv.push(s.into());
+            } else {
+                argv.push(format!("--{}={}", key, &s).into());
+            }
+        }
+
+        args.push((arg.get_id().to_string(), argv));
+    }
+
+    Ok(args)
+}
+
+impl Config {
+    /// Read the configuration file at `path`. `cmd` (the command-line
+    /// parser) is used to check and translate the options.
+    fn read(path: &Path, cmd: &Command) -> Result<Config, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("unable to read \"{}\": {}", path.display(), &e))?;
+        let table: Table = text
+            .parse()
+            .map_err(|e| format!("error in \"{}\": {}", path.display(), &e))?;
+
+        let mut cfg = Config::default();
+        for (key, val) in table.iter() {
+            match (key.as_str(), val) {
+                ("defaults", Value::Table(t)) => {
+                    cfg.defaults = table_args(t, cmd, path)?;
+                }
+                ("searches", Value::Table(t)) => {
+                    for (name, val) in t.iter() {
+                        match val {
+                            Value::Table(t) => {
+                                cfg.searches.insert(name.clone(), table_args(t, cmd, path)?);
+                            }
+                            _ => {
+                                return Err(format!(
+                                    "{}: search \"{}\" should be a table",
+                                    path.display(),
+                                    name
+                                ))
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "{}: unexpected entry \"{}\"; expected [defaults] or [searches.NAME]",
+                        path.display(),
+                        key
+                    ))
+                }
+            }
+        }
+
+        Ok(cfg)
+    }
+
+    /// Read the user's and the current project's configuration files,
+    /// whichever of them exist.
+    pub fn load(cmd: &Command) -> Result<Config, String> {
+        let mut cfg = Config::default();
+        for path in [user_path(), local_path()].iter().flatten() {
+            if path.is_file() {
+                let other = Config::read(path, cmd)?;
+                merge_args(&mut cfg.defaults, other.defaults);
+                for (name, args) in other.searches.into_iter() {
+                    cfg.searches.insert(name, args);
+                }
+            }
+        }
+
+        Ok(cfg)
+    }
+
+    /**
+    The command-line arguments equivalent to the defaults plus the named
+    `search` (if any), minus any options already `given` on the actual
+    command line.
+    */
+    pub fn args(&self, search: Option<&str>, given: &ArgMatches) -> Result<Vec<OsString>, String> {
+        let mut args = self.defaults.clone();
+        if let Some(name) = search {
+            match self.searches.get(name) {
+                Some(search) => merge_args(&mut args, search.clone()),
+                None => return Err(format!("no saved search named \"{}\"", name)),
+            }
+        }
+
+        Ok(args
+            .into_iter()
+            .filter(|(id, _)| given.value_source(id) != Some(ValueSource::CommandLine))
+            .flat_map(|(_, argv)| argv.into_iter())
+            .collect())
+    }
+}
@@ -0,0 +1,117 @@
+/*!
+Controlling the form in which matched paths are printed.
+*/
+use std::{
+    borrow::Cow,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use fine::archive;
+
+/// How matched paths should be displayed.
+#[derive(Clone, Debug)]
+pub enum PathStyle {
+    /// exactly as found (starting with the base directory)
+    AsFound,
+    /// relative to the base directory
+    StripBase(PathBuf),
+    /// relative to the (absolute, normalized) directory `dir`
+    RelativeTo { cwd: PathBuf, dir: PathBuf },
+    /// absolute, made by joining the path to the current directory
+    Absolute { cwd: PathBuf },
+    /// absolute, with all symbolic links resolved
+    Canonical,
+}
+
+/// The current directory as the user's shell knows it (that is, by way of
+/// any symbolic links they went through to get there), if possible.
+pub fn current_dir() -> io::Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    if let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from) {
+        if pwd.is_absolute() && pwd.canonicalize().ok() == cwd.canonicalize().ok() {
+            return Ok(pwd);
+        }
+    }
+    Ok(cwd)
+}
+
+/**
+Remove `.` and `..` components from a path without consulting the
+filesystem. (This means that `..` after a symbolic link goes back to the
+link's parent, which is usually what a user expects, but not always what
+the OS would do.)
+*/
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Find the path to `path` from `dir`; both should be absolute and
+/// normalized.
+fn relative(path: &Path, dir: &Path) -> PathBuf {
+    let mut path_comps = path.components().peekable();
+    let mut dir_comps = dir.components().peekable();
+    while let (Some(a), Some(b)) = (path_comps.peek(), dir_comps.peek()) {
+        if a != b {
+            break;
+        }
+        path_comps.next();
+        dir_comps.next();
+    }
+
+    let mut rel: PathBuf = dir_comps.map(|_| Component::ParentDir).collect();
+    rel.extend(path_comps);
+    if rel.as_os_str().is_empty() {
+        rel.push(Component::CurDir);
+    }
+    rel
+}
+
+impl PathStyle {
+    /// The form of `path` to print. Returns `None` if it can't be
+    /// determined (which should be rare).
+    pub fn show<'a>(&self, path: &'a Path) -> Option<Cow<'a, Path>> {
+        match self {
+            PathStyle::AsFound => Some(Cow::Borrowed(path)),
+            PathStyle::StripBase(base) => match path.strip_prefix(base) {
+                Ok(rel) if rel.as_os_str().is_empty() => Some(Cow::Borrowed(Path::new("."))),
+                Ok(rel) => Some(Cow::Borrowed(rel)),
+                Err(_) => Some(Cow::Borrowed(path)),
+            },
+            PathStyle::RelativeTo { cwd, dir } => {
+                let abs = normalize(&cwd.join(path));
+                Some(Cow::Owned(relative(&abs, dir)))
+            }
+            PathStyle::Absolute { cwd } => Some(Cow::Owned(normalize(&cwd.join(path)))),
+            PathStyle::Canonical => match path.canonicalize() {
+                Ok(abs) => Some(Cow::Owned(abs)),
+                // Archive members aren't on disk, but their archives are.
+                Err(_) => match archive::split(path) {
+                    Some((arch, member)) => {
+                        let mut abs = arch.canonicalize().ok()?.into_os_string();
+                        abs.push(archive::SEPARATOR);
+                        abs.push(member);
+                        Some(Cow::Owned(PathBuf::from(abs)))
+                    }
+                    // Neither are entries that have been removed (as
+                    // reported from a snapshot), but some ancestor is.
+                    None => {
+                        let dir = path.ancestors().skip(1).find(|dir| dir.exists())?;
+                        let rest = path.strip_prefix(dir).ok()?;
+                        Some(Cow::Owned(dir.canonicalize().ok()?.join(rest)))
+                    }
+                },
+            },
+        }
+    }
+}
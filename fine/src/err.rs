@@ -0,0 +1,85 @@
+/*!
+Error type for the library.
+*/
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    io,
+    path::PathBuf,
+};
+
+#[derive(Debug)]
+pub enum FineErr {
+    /// Something is wrong with the way the search was set up
+    /// (bad patterns, nonsensical time bounds, missing base directory,
+    /// that sort of thing).
+    Config(String),
+    /// The path couldn't be read because of its permissions.
+    PermissionDenied(PathBuf),
+    /// The path disappeared (or never existed).
+    NotFound(PathBuf),
+    /// Following a symbolic link at `child` leads back to `ancestor`.
+    Loop { ancestor: PathBuf, child: PathBuf },
+    /// Any other I/O error encountered dealing with the path.
+    Io(PathBuf, io::Error),
+}
+
+impl FineErr {
+    /// Sort an I/O error involving `path` into the appropriate variant.
+    pub fn from_io<P: Into<PathBuf>>(path: P, e: io::Error) -> FineErr {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => FineErr::PermissionDenied(path.into()),
+            io::ErrorKind::NotFound => FineErr::NotFound(path.into()),
+            _ => FineErr::Io(path.into(), e),
+        }
+    }
+}
+
+impl From<walkdir::Error> for FineErr {
+    fn from(e: walkdir::Error) -> Self {
+        let path = e.path().map(PathBuf::from).unwrap_or_default();
+        if let Some(ancestor) = e.loop_ancestor() {
+            return FineErr::Loop {
+                ancestor: ancestor.to_path_buf(),
+                child: path,
+            };
+        }
+
+        let msg = format!("{}", &e);
+        match e.into_io_error() {
+            Some(ioe) => FineErr::from_io(path, ioe),
+            // Walkdir always wraps an io::Error except in the loop case,
+            // but just in case that ever changes:
+            None => FineErr::Io(path, io::Error::new(io::ErrorKind::Other, msg)),
+        }
+    }
+}
+
+impl Display for FineErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FineErr::Config(ref msg) => write!(f, "{}", msg),
+            FineErr::PermissionDenied(ref p) => write!(f, "{}: permission denied", p.display()),
+            FineErr::NotFound(ref p) => write!(f, "{}: not found", p.display()),
+            FineErr::Loop {
+                ref ancestor,
+                ref child,
+            } => write!(
+                f,
+                "{}: filesystem loop back to {}",
+                child.display(),
+                ancestor.display()
+            ),
+            FineErr::Io(ref p, ref e) => write!(f, "{}: {}", p.display(), e),
+        }
+    }
+}
+
+impl Error for FineErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FineErr::Io(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
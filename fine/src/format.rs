@@ -0,0 +1,120 @@
+/*!
+Custom output formats, specified with `--format`.
+
+The format string is printed for each match, with the following
+replacements:
+
+  * `{path}`: the path of the match
+  * `{xattr:NAME}`: the value of the match's extended attribute `NAME`
+    (or nothing, if it doesn't have one); Unix-only
+  * `{{` and `}}`: literal braces
+*/
+use std::{convert::TryFrom, path::Path};
+#[cfg(unix)]
+use std::ffi::OsString;
+
+use bstr::ByteSlice;
+#[cfg(unix)]
+use fine::types::get_xattr;
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(String),
+    Path,
+    #[cfg(unix)]
+    Xattr(OsString),
+}
+
+/// A parsed output format.
+#[derive(Clone, Debug)]
+pub struct Format(Vec<Piece>);
+
+impl Format {
+    /**
+    Render the format for the match at `path`. `shown` is the form of the
+    path that should actually be printed (which may be different, if, say,
+    it's supposed to be absolute).
+    */
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub fn render(&self, shown: &Path, path: &Path) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        for piece in self.0.iter() {
+            match piece {
+                Piece::Literal(s) => out.extend_from_slice(s.as_bytes()),
+                Piece::Path => {
+                    if let Some(b) = <[u8]>::from_path(shown) {
+                        out.extend_from_slice(b);
+                    }
+                }
+                #[cfg(unix)]
+                Piece::Xattr(name) => {
+                    if let Ok(Some(v)) = get_xattr(path, name) {
+                        out.extend_from_slice(&v);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Interpret the contents of a `{...}` placeholder.
+fn placeholder(s: &str) -> Result<Piece, String> {
+    if s == "path" {
+        return Ok(Piece::Path);
+    }
+    if let Some(name) = s.strip_prefix("xattr:") {
+        #[cfg(unix)]
+        return Ok(Piece::Xattr(name.into()));
+        #[cfg(not(unix))]
+        return Err(format!(
+            "{{xattr:{}}}: extended attributes are not supported on this platform",
+            name
+        ));
+    }
+    Err(format!(
+        "unknown format placeholder {{{}}}\npossible values are: {{path}}, {{xattr:NAME}}",
+        s
+    ))
+}
+
+/// The `TryFrom` impl is used in parsing user input.
+impl TryFrom<&str> for Format {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let mut pieces = Vec::new();
+        let mut lit = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    lit.push(c);
+                }
+                ('{', _) => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(format!("unclosed {{ in format \"{}\"", s)),
+                        }
+                    }
+                    if !lit.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                    }
+                    pieces.push(placeholder(&inner)?);
+                }
+                ('}', _) => return Err(format!("unmatched }} in format \"{}\"", s)),
+                (c, _) => lit.push(c),
+            }
+        }
+        if !lit.is_empty() {
+            pieces.push(Piece::Literal(lit));
+        }
+
+        Ok(Format(pieces))
+    }
+}
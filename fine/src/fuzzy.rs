@@ -0,0 +1,49 @@
+/*!
+Fuzzy matching, the way editors' file pickers do it.
+
+A query matches a name if its characters appear in the name in order
+(but not necessarily together); matches are scored by how "good" they
+are (consecutive characters, characters at the starts of words, and so
+on) so they can be ranked.
+*/
+use std::{
+    fmt::{Debug, Formatter},
+    path::Path,
+};
+
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
+/// Scores paths against a set of fuzzy queries.
+pub(crate) struct Fuzzy {
+    matcher: SkimMatcherV2,
+    queries: Vec<String>,
+}
+
+impl Fuzzy {
+    /// Queries are case-insensitive unless they contain uppercase
+    /// characters.
+    pub(crate) fn new(queries: Vec<String>) -> Fuzzy {
+        Fuzzy {
+            matcher: SkimMatcherV2::default().smart_case(),
+            queries,
+        }
+    }
+
+    /// Score `target` against the queries, returning the best score of
+    /// any that match.
+    pub(crate) fn score(&self, target: &Path) -> Option<i64> {
+        let target = target.to_string_lossy();
+        self.queries
+            .iter()
+            .filter_map(|q| self.matcher.fuzzy_match(&target, q))
+            .max()
+    }
+}
+
+impl Debug for Fuzzy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Fuzzy")
+            .field("queries", &self.queries)
+            .finish()
+    }
+}
@@ -0,0 +1,206 @@
+/*!
+Filtering by status in a git repository.
+
+The repository's index and working tree status are read (once, when the
+search is built) directly with `libgit2`, so there's no need for `git`
+itself to be installed.
+*/
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
+
+use bstr::ByteSlice;
+use enum_iterator::{all, Sequence};
+use git2::{Repository, Status, StatusOptions};
+
+use crate::FineErr;
+
+/// Status of a path in a git repository.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Sequence)]
+pub enum GitState {
+    /// in the index
+    Tracked,
+    /// neither in the index nor ignored
+    Untracked,
+    /// changed in the working tree, but the change hasn't been staged
+    Modified,
+    /// changed in the index relative to `HEAD`
+    Staged,
+    /// matched by a `.gitignore` (or similar) rule
+    Ignored,
+}
+
+impl GitState {
+    pub fn as_str(&self) -> &'static str {
+        use GitState::*;
+
+        match self {
+            Tracked => "tracked",
+            Untracked => "untracked",
+            Modified => "modified",
+            Staged => "staged",
+            Ignored => "ignored",
+        }
+    }
+
+    /// The status flags that put a path in this state. (Tracked files
+    /// are read straight from the index, so they don't have any.)
+    fn flags(&self) -> Status {
+        use GitState::*;
+
+        match self {
+            Tracked => Status::empty(),
+            Untracked => Status::WT_NEW,
+            Modified => Status::WT_MODIFIED | Status::WT_TYPECHANGE | Status::WT_RENAMED,
+            Staged => {
+                Status::INDEX_NEW
+                    | Status::INDEX_MODIFIED
+                    | Status::INDEX_RENAMED
+                    | Status::INDEX_TYPECHANGE
+            }
+            Ignored => Status::IGNORED,
+        }
+    }
+}
+
+/// The `TryFrom` impl is used in parsing user input.
+impl TryFrom<&str> for GitState {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        use GitState::*;
+
+        match s.to_ascii_lowercase().as_str() {
+            "t" | "tracked" => Ok(Tracked),
+            "u" | "untracked" | "new" => Ok(Untracked),
+            "m" | "modified" | "changed" => Ok(Modified),
+            "s" | "staged" | "cached" => Ok(Staged),
+            "i" | "ignored" => Ok(Ignored),
+            _ => {
+                let allowed: Vec<&str> = all::<GitState>().map(|t| t.as_str()).collect();
+                Err(format!(
+                    "git state {} invalid\npossible values are: {}",
+                    s,
+                    &allowed.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+fn git_err(e: git2::Error) -> FineErr {
+    FineErr::Config(format!("git error: {}", e.message()))
+}
+
+/**
+The set of paths in a repository that are in any of a set of states.
+
+Directories count as being in a state if anything inside them is.
+*/
+#[derive(Debug)]
+pub(crate) struct GitFilter {
+    /// Canonical version of the search's base directory.
+    base: PathBuf,
+    /// Absolute paths of everything in any of the requested states.
+    paths: HashSet<PathBuf>,
+}
+
+impl GitFilter {
+    /// Read the status of the repository containing `base`.
+    pub(crate) fn new(base: &Path, states: &[GitState]) -> Result<GitFilter, FineErr> {
+        let base = base
+            .canonicalize()
+            .map_err(|e| FineErr::from_io(base, e))?;
+        let repo = Repository::discover(&base).map_err(|_| {
+            FineErr::Config(format!(
+                "\"{}\" is not in a git repository",
+                base.display()
+            ))
+        })?;
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| FineErr::Config("can't filter by git status in a bare repository".into()))?;
+        let workdir = workdir
+            .canonicalize()
+            .map_err(|e| FineErr::from_io(workdir, e))?;
+
+        let mut filter = GitFilter {
+            base,
+            paths: HashSet::new(),
+        };
+
+        if states.contains(&GitState::Tracked) {
+            let index = repo.index().map_err(git_err)?;
+            for ent in index.iter() {
+                filter.insert(&workdir, &ent.path);
+            }
+        }
+
+        let flags = states
+            .iter()
+            .fold(Status::empty(), |acc, s| acc | s.flags());
+        if !flags.is_empty() {
+            let mut opts = StatusOptions::new();
+            opts.include_untracked(states.contains(&GitState::Untracked))
+                .recurse_untracked_dirs(true)
+                .include_ignored(states.contains(&GitState::Ignored))
+                .recurse_ignored_dirs(true)
+                .exclude_submodules(true);
+            let statuses = repo.statuses(Some(&mut opts)).map_err(git_err)?;
+            for st in statuses.iter() {
+                if st.status().intersects(flags) {
+                    filter.insert(&workdir, st.path_bytes());
+                }
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// Add a path (relative to the repository's `workdir`, as reported by
+    /// `libgit2`) and all its ancestor directories.
+    fn insert(&mut self, workdir: &Path, rel: &[u8]) {
+        let rel = match rel.to_path() {
+            Ok(rel) => rel,
+            Err(_) => return,
+        };
+        let mut path = workdir.join(rel);
+        // If a directory is already present, all of its ancestors are, too.
+        while self.paths.insert(path.clone()) && path != workdir {
+            if !path.pop() {
+                break;
+            }
+        }
+    }
+
+    /**
+    Whether `path` is in any of the requested states. Paths that start
+    with the search's `base` are looked up relative to it; any others
+    (like ones listed on stdin) are resolved against the current directory.
+    */
+    pub(crate) fn matches(&self, base: &Path, path: &Path) -> bool {
+        match path.strip_prefix(base) {
+            Ok(rel) => self.paths.contains(&self.base.join(rel)),
+            Err(_) => match resolve(path) {
+                Some(path) => self.paths.contains(&path),
+                None => false,
+            },
+        }
+    }
+}
+
+/// The absolute path of `path`, with its directory canonicalized (but not
+/// the last component, because git tracks symbolic links themselves).
+fn resolve(path: &Path) -> Option<PathBuf> {
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return path.canonicalize().ok(),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(dir.canonicalize().ok()?.join(name))
+}
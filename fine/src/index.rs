@@ -0,0 +1,558 @@
+/*!
+A `locate`-style index of a directory tree, for searching big trees
+repeatedly without walking them every time.
+
+The index records, for every directory in the tree, its modification time
+and the name, type, and modification time of each of its entries. When an
+index is refreshed, directories whose modification times haven't changed
+are assumed to have the same contents, and aren't read again. (Like
+`locate`'s database, this means the recorded modification times of
+_files_ can lag behind until something is added to or removed from their
+directories.)
+
+## Format
+
+All integers are unsigned LEB128 varints; signed integers are zigzag
+encoded first. Byte strings are a length followed by that many bytes.
+
+```text
+magic:     b"FINEIDX\x02"
+base:      byte string (canonical path of the indexed directory)
+records:   (until the end of the file)
+    path:      byte string (path of the directory relative to the base)
+    mtime:     signed seconds, nanoseconds
+    n_entries: integer
+    entries:   (n_entries of them)
+        name:      byte string
+        type:      one byte (see below)
+        mtime:     signed seconds, nanoseconds
+```
+
+Entry types are stored as one of the letters `f` (file), `d` (directory),
+`l` (link), `x` (broken link), `p` (FIFO), `s` (socket), `b` (block
+device), or `c` (character device).
+*/
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bstr::ByteSlice;
+use enum_iterator::all;
+
+use crate::{
+    times::{from_unix, to_unix},
+    types::EType,
+    Finder, FineErr,
+};
+
+static MAGIC: &[u8] = b"FINEIDX\x02";
+
+/// An entry in an indexed directory.
+#[derive(Clone, Debug)]
+struct Record {
+    name: Vec<u8>,
+    etype: EType,
+    mtime: SystemTime,
+}
+
+/// An indexed directory.
+#[derive(Clone, Debug)]
+struct DirRecord {
+    /// Path relative to the index's base.
+    path: PathBuf,
+    mtime: SystemTime,
+    entries: Vec<Record>,
+}
+
+/// A snapshot of the paths (and a little metadata) in a directory tree.
+#[derive(Clone, Debug)]
+pub struct Index {
+    base: PathBuf,
+    dirs: Vec<DirRecord>,
+}
+
+fn bad_index(path: &Path) -> FineErr {
+    FineErr::Config(format!("\"{}\" is not a valid index file", path.display()))
+}
+
+pub(crate) fn write_uint<W: Write>(w: &mut W, mut n: u64) -> std::io::Result<()> {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub(crate) fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> std::io::Result<()> {
+    write_uint(w, b.len() as u64)?;
+    w.write_all(b)
+}
+
+pub(crate) fn write_time<W: Write>(w: &mut W, t: SystemTime) -> std::io::Result<()> {
+    let (secs, nanos) = to_unix(t);
+    write_uint(w, ((secs << 1) ^ (secs >> 63)) as u64)?;
+    write_uint(w, nanos as u64)
+}
+
+/// The byte that stands for `etype` in an index or a snapshot.
+fn etype_code(etype: EType) -> u8 {
+    use EType::*;
+
+    match etype {
+        File => b'f',
+        Dir => b'd',
+        Link => b'l',
+        Broken => b'x',
+        #[cfg(unix)]
+        Fifo => b'p',
+        #[cfg(any(unix, target_os = "wasi"))]
+        Socket => b's',
+        #[cfg(any(unix, target_os = "wasi"))]
+        Block => b'b',
+        #[cfg(any(unix, target_os = "wasi"))]
+        Char => b'c',
+    }
+}
+
+pub(crate) fn write_etype<W: Write>(w: &mut W, etype: EType) -> std::io::Result<()> {
+    w.write_all(&[etype_code(etype)])
+}
+
+/// Reads the pieces of an index (or a [`Snapshot`](crate::Snapshot))
+/// back out of its bytes.
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn uint(&mut self) -> Option<u64> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.bytes.split_first()?;
+            self.bytes = rest;
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(n);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = usize::try_from(self.uint()?).ok()?;
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (b, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(b)
+    }
+
+    pub(crate) fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(byte)
+    }
+
+    pub(crate) fn time(&mut self) -> Option<SystemTime> {
+        let z = self.uint()?;
+        let secs = ((z >> 1) as i64) ^ -((z & 1) as i64);
+        let nanos = u32::try_from(self.uint()?).ok()?;
+        Some(from_unix(secs, nanos))
+    }
+
+    pub(crate) fn etype(&mut self) -> Option<EType> {
+        let c = self.byte()?;
+        all::<EType>().find(|&t| etype_code(t) == c)
+    }
+
+    fn dir(&mut self) -> Option<DirRecord> {
+        let path = self.bytes()?.to_path().ok()?.to_path_buf();
+        let mtime = self.time()?;
+        let n = usize::try_from(self.uint()?).ok()?;
+        let mut entries = Vec::with_capacity(n.min(self.bytes.len()));
+        for _ in 0..n {
+            let name = self.bytes()?.to_vec();
+            let etype = self.etype()?;
+            let mtime = self.time()?;
+            entries.push(Record { name, etype, mtime });
+        }
+        Some(DirRecord {
+            path,
+            mtime,
+            entries,
+        })
+    }
+}
+
+impl Index {
+    /**
+    Index the tree under `base`.
+
+    If a `previous` index of the same tree is supplied, directories that
+    haven't been modified since it was built are copied from it instead
+    of being read again. Errors reading individual directories and entries
+    are passed to `on_error`, and the offending items left out.
+    */
+    pub fn build<F>(base: &Path, previous: Option<&Index>, mut on_error: F) -> Result<Index, FineErr>
+    where
+        F: FnMut(FineErr),
+    {
+        let base = base.canonicalize().map_err(|e| {
+            FineErr::Config(format!("unable to read from \"{}\": {}", base.display(), &e))
+        })?;
+        if !base.is_dir() {
+            return Err(FineErr::Config(format!(
+                "\"{}\" is not a directory",
+                base.display()
+            )));
+        }
+
+        let old: HashMap<&Path, &DirRecord> = match previous {
+            Some(idx) if idx.base == base => {
+                idx.dirs.iter().map(|d| (d.path.as_path(), d)).collect()
+            }
+            _ => HashMap::new(),
+        };
+
+        let mut dirs: Vec<DirRecord> = Vec::new();
+        let mut stack: Vec<PathBuf> = vec![PathBuf::new()];
+        while let Some(rel) = stack.pop() {
+            let full = base.join(&rel);
+            let mtime = match full.symlink_metadata().and_then(|m| m.modified()) {
+                Ok(t) => t,
+                Err(e) => {
+                    on_error(FineErr::from_io(full, e));
+                    continue;
+                }
+            };
+
+            let entries = match old.get(rel.as_path()) {
+                Some(rec) if rec.mtime == mtime => rec.entries.clone(),
+                _ => match read_dir(&full, &mut on_error) {
+                    Some(entries) => entries,
+                    None => continue,
+                },
+            };
+
+            for ent in entries.iter() {
+                if ent.etype == EType::Dir {
+                    if let Ok(name) = ent.name.to_path() {
+                        stack.push(rel.join(name));
+                    }
+                }
+            }
+            dirs.push(DirRecord {
+                path: rel,
+                mtime,
+                entries,
+            });
+        }
+
+        Ok(Index { base, dirs })
+    }
+
+    /// Read an index from the file at `path`.
+    pub fn read(path: &Path) -> Result<Index, FineErr> {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| FineErr::from_io(path, e))?;
+
+        let rest = bytes.strip_prefix(MAGIC).ok_or_else(|| bad_index(path))?;
+        let mut r = Reader { bytes: rest };
+        let base = r
+            .bytes()
+            .and_then(|b| b.to_path().ok())
+            .ok_or_else(|| bad_index(path))?
+            .to_path_buf();
+
+        let mut dirs = Vec::new();
+        while !r.bytes.is_empty() {
+            dirs.push(r.dir().ok_or_else(|| bad_index(path))?);
+        }
+
+        Ok(Index { base, dirs })
+    }
+
+    /// Write the index to the file at `path`. It's written to a
+    /// temporary file first, and then moved into place, so a failure
+    /// won't clobber an existing index.
+    pub fn write(&self, path: &Path) -> Result<(), FineErr> {
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp = PathBuf::from(tmp_name);
+
+        self.write_to(&tmp)
+            .map_err(|e| FineErr::from_io(&tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| FineErr::from_io(path, e))
+    }
+
+    fn write_to(&self, path: &Path) -> std::io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_bytes(&mut w, <[u8]>::from_path(&self.base).unwrap_or_default())?;
+
+        for dir in self.dirs.iter() {
+            write_bytes(&mut w, <[u8]>::from_path(&dir.path).unwrap_or_default())?;
+            write_time(&mut w, dir.mtime)?;
+            write_uint(&mut w, dir.entries.len() as u64)?;
+            for ent in dir.entries.iter() {
+                write_bytes(&mut w, &ent.name)?;
+                write_etype(&mut w, ent.etype)?;
+                write_time(&mut w, ent.mtime)?;
+            }
+        }
+
+        w.flush()
+    }
+
+    /// The (canonical) path of the indexed directory.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The number of entries in the index.
+    pub fn len(&self) -> usize {
+        self.dirs.iter().map(|d| d.entries.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.iter().all(|d| d.entries.is_empty())
+    }
+}
+
+/// Read the entries of the directory at `path`.
+fn read_dir<F>(path: &Path, on_error: &mut F) -> Option<Vec<Record>>
+where
+    F: FnMut(FineErr),
+{
+    let rd = match std::fs::read_dir(path) {
+        Ok(rd) => rd,
+        Err(e) => {
+            on_error(FineErr::from_io(path, e));
+            return None;
+        }
+    };
+
+    let mut entries = Vec::new();
+    for res in rd {
+        let ent = match res {
+            Ok(ent) => ent,
+            Err(e) => {
+                on_error(FineErr::from_io(path, e));
+                continue;
+            }
+        };
+        let meta = match ent.path().symlink_metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                on_error(FineErr::from_io(ent.path(), e));
+                continue;
+            }
+        };
+        // Anything we can't classify (and thus can't filter by type)
+        // is just left out.
+        let etype = match EType::of(&meta.file_type()) {
+            Some(t) => t,
+            None => continue,
+        };
+        let mtime = match meta.modified() {
+            Ok(t) => t,
+            Err(e) => {
+                on_error(FineErr::from_io(ent.path(), e));
+                continue;
+            }
+        };
+        let name = match <[u8]>::from_os_str(&ent.file_name()) {
+            Some(b) => b.to_vec(),
+            None => continue,
+        };
+
+        entries.push(Record { name, etype, mtime });
+    }
+
+    Some(entries)
+}
+
+impl Finder {
+    /**
+    Search an [`Index`] instead of walking the directory tree.
+
+    The `Finder` should have been built with the index's
+    [`base`](Index::base) as its base directory.
+    */
+    pub fn search_index<'a>(&'a self, index: &'a Index) -> IndexMatches<'a> {
+        IndexMatches {
+            finder: self,
+            index,
+            dir: 0,
+            ent: None,
+            pruned: Vec::new(),
+        }
+    }
+}
+
+/// Iterator over the paths in an [`Index`] that match a [`Finder`];
+/// returned by [`Finder::search_index`].
+pub struct IndexMatches<'a> {
+    finder: &'a Finder,
+    index: &'a Index,
+    /// Index of the directory currently being examined.
+    dir: usize,
+    /// Index of the next entry in the current directory; `None` means
+    /// the directory itself still needs to be examined.
+    ent: Option<usize>,
+    /// Matching directories (relative to the base) not to look inside,
+    /// if pruning.
+    pruned: Vec<PathBuf>,
+}
+
+impl<'a> Iterator for IndexMatches<'a> {
+    type Item = Result<PathBuf, FineErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let base = self.finder.base();
+        loop {
+            let dir = self.index.dirs.get(self.dir)?;
+            let depth = dir.path.components().count();
+            // Directories always come after their parents in an index.
+            if self.ent.is_none() && self.pruned.iter().any(|p| dir.path.starts_with(p)) {
+                self.dir += 1;
+                continue;
+            }
+
+            let (path, etype, mtime) = match self.ent {
+                // The base directory itself is the only one that isn't
+                // an entry in any other directory.
+                None => {
+                    self.ent = Some(0);
+                    if depth != 0 {
+                        continue;
+                    }
+                    (base.to_path_buf(), EType::Dir, dir.mtime)
+                }
+                Some(n) => match dir.entries.get(n) {
+                    None => {
+                        self.dir += 1;
+                        self.ent = None;
+                        continue;
+                    }
+                    Some(rec) => {
+                        self.ent = Some(n + 1);
+                        if let Some(max) = self.finder.max_depth() {
+                            if depth + 1 > max {
+                                continue;
+                            }
+                        }
+                        let name = match rec.name.to_path() {
+                            Ok(name) => name,
+                            Err(_) => continue,
+                        };
+                        (base.join(&dir.path).join(name), rec.etype, rec.mtime)
+                    }
+                },
+            };
+
+            match self.finder.filter(&path, &etype, || Ok(mtime)) {
+                Ok(true) => {
+                    if self.finder.prunes() && etype == EType::Dir {
+                        match path.strip_prefix(base) {
+                            // That's the base directory; there's nothing
+                            // else to look at.
+                            Ok(rel) if rel.as_os_str().is_empty() => {
+                                self.dir = self.index.dirs.len()
+                            }
+                            Ok(rel) => self.pruned.push(rel.to_path_buf()),
+                            Err(_) => {}
+                        }
+                    }
+                    return Some(Ok(path));
+                }
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etype_codes() {
+        for t in all::<EType>() {
+            let mut buff = Vec::new();
+            write_etype(&mut buff, t).unwrap();
+            let mut r = Reader { bytes: &buff };
+            assert_eq!(r.etype(), Some(t));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("fine-index-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/file"), b"text").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("sub/file", dir.join("link")).unwrap();
+            let made = std::process::Command::new("mkfifo")
+                .arg(dir.join("pipe"))
+                .status()
+                .unwrap();
+            assert!(made.success());
+        }
+
+        let index = Index::build(&dir, None, |e| panic!("{}", &e)).unwrap();
+        let db = dir.with_extension("db");
+        index.write(&db).unwrap();
+        let read = Index::read(&db).unwrap();
+        std::fs::remove_file(&db).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let entries = |idx: &Index| {
+            let mut v: Vec<(PathBuf, Vec<u8>, EType, SystemTime)> = idx
+                .dirs
+                .iter()
+                .flat_map(|d| {
+                    d.entries
+                        .iter()
+                        .map(move |e| (d.path.clone(), e.name.clone(), e.etype, e.mtime))
+                })
+                .collect();
+            v.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+            v
+        };
+        assert_eq!(read.base(), index.base());
+        assert_eq!(entries(&read), entries(&index));
+
+        let types: Vec<EType> = entries(&read).into_iter().map(|e| e.2).collect();
+        assert!(types.contains(&EType::File));
+        assert!(types.contains(&EType::Dir));
+        #[cfg(unix)]
+        {
+            assert!(types.contains(&EType::Link));
+            assert!(types.contains(&EType::Fifo));
+        }
+    }
+
+    #[test]
+    fn old_magic() {
+        let db = std::env::temp_dir().join(format!("fine-index-old-{}.db", std::process::id()));
+        std::fs::write(&db, b"FINEIDX\x01\x00").unwrap();
+        let res = Index::read(&db);
+        std::fs::remove_file(&db).unwrap();
+        assert!(res.is_err());
+    }
+}
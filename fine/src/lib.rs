@@ -0,0 +1,38 @@
+/*!
+The search engine behind the `fine` command-line utility.
+
+Build a [`Search`], turn it into a [`Finder`], and iterate over the
+matching [`Entry`]s:
+
+```no_run
+use fine::{types::EType, Search};
+
+let finder = Search::new("/usr/share")
+    .pattern("*.ttf")
+    .file_type(EType::File)
+    .depth(4)
+    .build()?;
+
+for res in finder.iter() {
+    match res {
+        Ok(ent) => println!("{}", ent.path().display()),
+        Err(e) => eprintln!("{}", &e),
+    }
+}
+# Ok::<(), fine::FineErr>(())
+```
+*/
+pub mod archive;
+mod err;
+mod fuzzy;
+pub mod git;
+mod index;
+mod search;
+mod snapshot;
+pub mod times;
+pub mod types;
+
+pub use err::FineErr;
+pub use index::{Index, IndexMatches};
+pub use search::{Entry, Finder, Matches, Search};
+pub use snapshot::{Change, ChangeKind, Snapshot};
@@ -1,139 +1,196 @@
+mod config;
+mod display;
+mod format;
 mod opt;
-pub mod times;
-pub mod types;
+mod report;
+mod select;
+mod watch;
 
 use std::{
     error::Error,
-    io::{stdout, Write},
+    io::{stdin, stdout, BufRead, Write},
     path::Path,
 };
 
-use bstr::ByteSlice;
-use walkdir::{DirEntry, WalkDir};
+use bstr::{ByteSlice, ByteVec};
+use fine::{FineErr, Index, Snapshot};
 
 use opt::Opts;
-use types::HasEType;
+use report::{Report, EXIT_ERRORS};
 
 static NEWLINE: &[u8] = b"\n";
 
-/// Print a matched path relative to the base search path.
-fn print_relative(path: &Path) -> Option<()> {
-    let bytes = <[u8]>::from_os_str(path.as_os_str())?;
+/// Print a matched path in the form requested by the user.
+fn print_path(opts: &Opts, path: &Path) -> Option<()> {
+    // Unlikely this will fail, so we'll just skip it if it does.
+    let shown = opts.paths.show(path)?;
     let mut stdout = stdout();
-    stdout.write_all(bytes).unwrap();
+    match opts.format {
+        Some(ref fmt) => stdout.write_all(&fmt.render(&shown, path)).unwrap(),
+        None => stdout
+            .write_all(<[u8]>::from_os_str(shown.as_os_str())?)
+            .unwrap(),
+    }
     stdout.write_all(NEWLINE).unwrap();
     Some(())
 }
 
-/// Print the absolute form of a matched path.
-fn print_absolute(path: &Path) -> Option<()> {
-    // Unlikely this will error, so we'll just skip it.
-    let abs = path.canonicalize().ok()?;
-    let bytes = <[u8]>::from_os_str(abs.as_os_str())?;
-    let mut stdout = stdout();
-    stdout.write_all(bytes).unwrap();
-    stdout.write_all(NEWLINE).unwrap();
-    Some(())
+/// Print (and tally) the results of a search. Fuzzy matches are ranked
+/// (and maybe limited) first.
+fn output<I, P>(opts: &Opts, results: I, report: &mut Report)
+where
+    I: Iterator<Item = Result<P, FineErr>>,
+    P: AsRef<Path>,
+{
+    if !opts.fuzzy {
+        for res in results {
+            match res {
+                Ok(p) => {
+                    report.matched();
+                    print_path(opts, p.as_ref());
+                }
+                Err(e) => report.error(e, opts.errors),
+            }
+        }
+        return;
+    }
+
+    let mut ranked: Vec<(i64, P)> = Vec::new();
+    for res in results {
+        match res {
+            Ok(p) => {
+                let score = opts.finder.score(p.as_ref()).unwrap_or_default();
+                ranked.push((score, p));
+            }
+            Err(e) => report.error(e, opts.errors),
+        }
+    }
+    // Best scores first, and the shortest paths first among equals.
+    ranked.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then(a.as_ref().as_os_str().len().cmp(&b.as_ref().as_os_str().len()))
+    });
+    if let Some(n) = opts.limit {
+        ranked.truncate(n);
+    }
+
+    for (_, p) in ranked.iter() {
+        report.matched();
+        print_path(opts, p.as_ref());
+    }
 }
 
-/// Deterime whether a given filename matches the supplied
-/// set of patterns.
-fn check_match(opts: &Opts, ent: &DirEntry) -> Option<bool> {
-    let path = ent.path();
-    let path_to_match = if opts.full {
-        path.as_os_str()
-    } else {
-        path.file_name()?
+/// Walk the directory tree starting from the base directory, printing
+/// matching paths.
+fn walk_and_check(opts: &Opts, report: &mut Report) {
+    output(opts, opts.finder.iter(), report)
+}
+
+/// Check the paths listed on stdin (separated by newlines, or by NULs with
+/// `--null`), printing the ones that match.
+fn filter_stdin(opts: &Opts, report: &mut Report) {
+    let stdin = stdin();
+    let input = stdin.lock();
+    let sep = match opts.null {
+        true => 0,
+        false => b'\n',
     };
-    let bytes = <[u8]>::from_os_str(path_to_match)?;
 
-    Some(opts.patterns.is_match(bytes))
+    let results = input.split(sep).filter_map(|res| match res {
+        Ok(bytes) if bytes.is_empty() => None,
+        Ok(bytes) => match bytes.into_path_buf() {
+            Ok(path) => opts.finder.check_listed(&path).transpose(),
+            Err(e) => Some(Err(FineErr::Config(format!("invalid path from stdin: {}", &e)))),
+        },
+        Err(e) => Some(Err(FineErr::from_io("<stdin>", e))),
+    });
+    output(opts, results, report)
 }
 
-/// Walk the directory tree starting from `opts.base`, checking
-/// for and printing paths with matching filenames.
-fn walk_and_check(opts: &Opts) -> Result<(), Box<dyn Error>> {
-    let meta = std::fs::metadata(&opts.base)
-        .map_err(|e| format!("unable to read from \"{}\": {}", &opts.base.display(), &e))?;
-    if !meta.is_dir() {
-        return Err(format!("\"{}\" is not a directory", &opts.base.display()).into());
-    }
+/// Search the index, printing matching paths.
+fn search_index(opts: &Opts, index: &Index, report: &mut Report) {
+    output(opts, opts.finder.search_index(index), report)
+}
 
-    let ok_types = if opts.types.is_empty() {
-        None
+/// Build an index of the base directory and save it in `db`, reusing
+/// what's already there if `db` is an existing index.
+fn build_index(opts: &Opts, db: &Path, report: &mut Report) -> Result<(), FineErr> {
+    let previous = if db.exists() {
+        // If this fails, `db` is probably not an index, and we
+        // don't want to clobber it.
+        Some(Index::read(db)?)
     } else {
-        Some(opts.types.as_slice())
+        None
     };
 
-    let mut walker = WalkDir::new(&opts.base).follow_links(false);
-    if let Some(depth) = opts.depth {
-        walker = walker.max_depth(depth);
-    }
-
-    for res in walker {
-        let ent = match (res, opts.errors) {
-            (Ok(ent), _) => ent,
-            (Err(e), true) => {
-                eprintln!("{}", &e);
-                continue;
-            }
-            (Err(_), false) => continue,
-        };
+    let index = Index::build(opts.finder.base(), previous.as_ref(), |e| {
+        report.error(e, opts.errors)
+    })?;
+    index.write(db)?;
+    report.recorded(index.len());
 
-        if let Some(types) = ok_types {
-            if !ent.file_type().is_one(types) {
-                continue;
-            }
-        }
+    Ok(())
+}
 
-        if opts.mod_after.is_some() || opts.mod_before.is_some() {
-            let meta = match (ent.metadata(), opts.errors) {
-                (Ok(meta), _) => meta,
-                (Err(e), true) => {
-                    eprintln!("{}", &e);
-                    continue;
-                }
-                (Err(_), false) => continue,
-            };
-            let modtime = match (meta.modified(), opts.errors) {
-                (Ok(mtime), _) => mtime,
-                (Err(e), true) => {
-                    eprintln!("{}", &e);
-                    continue;
-                }
-                (Err(_), false) => continue,
-            };
+/// Take a snapshot of the matches and save it in `file`.
+fn take_snapshot(opts: &Opts, file: &Path, report: &mut Report) -> Result<(), FineErr> {
+    let snapshot = opts.finder.snapshot(opts.hash, |e| report.error(e, opts.errors))?;
+    snapshot.write(file)?;
+    report.recorded(snapshot.len());
 
-            if let Some(t) = opts.mod_after {
-                if modtime <= t {
-                    continue;
-                }
-            }
-            if let Some(t) = opts.mod_before {
-                if modtime >= t {
-                    continue;
-                }
-            }
-        }
+    Ok(())
+}
 
-        match (check_match(opts, &ent).unwrap_or_default(), opts.absolute) {
-            (true, false) => print_relative(ent.path()),
-            (true, true) => print_absolute(ent.path()),
-            _ => continue,
-        };
+/// Print the ways the matches have changed since the `old` snapshot was
+/// taken, each path preceded by the kind of change.
+fn diff_snapshot(opts: &Opts, old: &Snapshot, report: &mut Report) -> Result<(), FineErr> {
+    let new = opts.finder.snapshot(old.hashed(), |e| report.error(e, opts.errors))?;
+    let mut stdout = stdout();
+    for change in old.diff(&new).iter() {
+        report.matched();
+        stdout.write_all(change.kind.as_str().as_bytes()).unwrap();
+        stdout.write_all(b"\t").unwrap();
+        print_path(opts, &change.path);
     }
 
     Ok(())
 }
 
-fn wrapped_main() -> Result<(), Box<dyn Error>> {
+fn wrapped_main() -> Result<Report, Box<dyn Error>> {
     let opts = Opts::new()?;
-    walk_and_check(&opts)
+    let mut report = Report::default();
+    if opts.undo {
+        select::undo(&opts, &mut report)?;
+    } else if let Some(ref db) = opts.index_build {
+        build_index(&opts, db, &mut report)?;
+    } else if let Some(ref file) = opts.snapshot {
+        take_snapshot(&opts, file, &mut report)?;
+    } else if let Some(ref old) = opts.diff_snapshot {
+        diff_snapshot(&opts, old, &mut report)?;
+    } else if opts.select {
+        select::select(&opts, &mut report)?;
+    } else if let Some(ref index) = opts.index {
+        search_index(&opts, index, &mut report);
+    } else if opts.stdin {
+        filter_stdin(&opts, &mut report);
+    } else if opts.watch {
+        watch::watch(&opts, &mut report)?;
+    } else {
+        walk_and_check(&opts, &mut report);
+    }
+    stdout().flush()?;
+    report.summarize(opts.errors);
+
+    Ok(report)
 }
 
 fn main() {
-    if let Err(e) = wrapped_main() {
-        eprintln!("{}", &e);
-        std::process::exit(1);
+    match wrapped_main() {
+        Ok(report) => std::process::exit(report.exit_code()),
+        Err(e) => {
+            eprintln!("{}", &e);
+            std::process::exit(EXIT_ERRORS);
+        }
     }
 }
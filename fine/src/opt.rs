@@ -1,23 +1,37 @@
-/**!
+/*!
 Argument parsing and configutation.
 */
-use std::{convert::TryFrom, path::PathBuf, time::SystemTime};
+use std::{convert::TryFrom, ffi::OsString, path::PathBuf};
 
-use clap::Parser;
-use globset::Glob;
-use regex::bytes::RegexSet;
+use clap::{builder::Resettable, ArgGroup, CommandFactory, FromArgMatches, Parser};
+use fine::{
+    git::GitState,
+    times::{self, DateOrder, RefTime},
+    types::EType,
+    Finder, Index, Search, Snapshot,
+};
+#[cfg(unix)]
+use fine::types::{LinkCount, XattrPred};
 
-use crate::{times, types::*};
+use crate::{
+    config::Config,
+    display::{self, PathStyle},
+    format::Format,
+};
+
+/// Environment variable that can specify the default `--date-order`.
+static DATE_ORDER_VAR: &str = "FINE_DATE_ORDER";
 
 /// A more forgiving version of find; it works just fine.
 #[derive(Debug, Parser)]
-#[command(author, version, about)]
+#[command(author, version, about, args_override_self = true)]
+#[command(group(ArgGroup::new("REFERENCE").multiple(true)))]
 struct OptArgs {
     /// The pattern(s) to match file paths against.
     pattern: Vec<String>,
 
     /// Base directory in which to begin search.
-    #[arg(short, long, default_value_t = String::from("."))]
+    #[arg(short, long, default_value = ".")]
     base: String,
 
     /// Limit the search to this depth below <BASE>.
@@ -25,13 +39,31 @@ struct OptArgs {
     depth: Option<usize>,
 
     /// Use regex (instead of glob) matching.
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long)]
     regex: bool,
 
+    /// Use fuzzy matching, ranking results by how well they match.
+    #[arg(short = 'z', long, conflicts_with = "watch")]
+    fuzzy: bool,
+
+    /// With --fuzzy, print only the <N> best matches.
+    #[arg(short, long, name = "N", requires = "fuzzy")]
+    limit: Option<usize>,
+
     /// Match any part of the path, not just the filename.
     #[arg(short, long)]
     full: bool,
 
+    /// Match only entries inside a directory (below <BASE>) whose name
+    /// matches <PATTERN>.
+    #[arg(long, value_name = "PATTERN")]
+    in_dir: Vec<String>,
+
+    /// Match only entries whose immediate parent directory's name
+    /// matches <PATTERN>.
+    #[arg(long, value_name = "PATTERN")]
+    parent: Vec<String>,
+
     /// Match only against specified types [default is all].
     #[arg(short, long = "type", name = "TYPE")]
     types: Vec<String>,
@@ -44,102 +76,364 @@ struct OptArgs {
     #[arg(long, name = "END")]
     mod_before: Option<String>,
 
+    /// Match only files modified more recently than <FILE> was.
+    #[arg(long, name = "FILE", conflicts_with = "START", group = "REFERENCE")]
+    newer: Option<PathBuf>,
+
+    /// Match only files last modified before <FILE> was.
+    #[arg(long, name = "OLDFILE", value_name = "FILE", conflicts_with = "END", group = "REFERENCE")]
+    older: Option<PathBuf>,
+
+    /// Which time of the --newer/--older <FILE> to use: mtime, atime, or ctime.
+    #[arg(long, name = "WHICH", default_value = "mtime", requires = "REFERENCE")]
+    ref_time: String,
+
+    /// Match only files with this git status: tracked, untracked,
+    /// modified, staged, or ignored.
+    #[arg(short, long, name = "STATE")]
+    git: Vec<String>,
+
+    /// Also search inside .tar, .tar.gz, .tar.zst, and .zip archives.
+    #[arg(long)]
+    archives: bool,
+
+    /// Don't search inside matching directories (report only the
+    /// top-most of nested matches).
+    #[arg(long, conflicts_with = "watch")]
+    prune: bool,
+
+    /// Match only files with the extended attribute <NAME> (set to
+    /// <VALUE>, if given).
+    #[cfg(unix)]
+    #[arg(short = 'x', long, value_name = "NAME[=VALUE]")]
+    xattr: Vec<String>,
+
+    /// Match only files with this many hard links (like 2, >1, or <3).
+    #[cfg(unix)]
+    #[arg(long, value_name = "N")]
+    links: Option<String>,
+
+    /// Match only paths that are the same file as (hard links to) <PATH>.
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    same_file: Option<PathBuf>,
+
+    /// Match only files with POSIX ACLs.
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    acl: bool,
+
+    /// Order of numeric dates: dmy, mdy, or ymd [default: $FINE_DATE_ORDER or mdy]
+    #[arg(long, name = "ORDER")]
+    date_order: Option<String>,
+
     /// Print absolute paths. [default: relative to BASE]
-    #[arg(short, long)]
+    #[arg(short, long, overrides_with_all = ["strip_base", "relative_to"])]
     absolute: bool,
 
-    /// Show access errors (default is to ignore them).
+    /// With --absolute, don't resolve symbolic links (just join paths
+    /// to the current directory).
+    #[arg(long)]
+    no_resolve: bool,
+
+    /// Print paths relative to <BASE> (without it in front).
+    #[arg(long, overrides_with_all = ["absolute", "relative_to"])]
+    strip_base: bool,
+
+    /// Print paths relative to <DIR>.
+    #[arg(long, value_name = "DIR", overrides_with_all = ["absolute", "strip_base"])]
+    relative_to: Option<PathBuf>,
+
+    /// Print matches in this format, with {path} replaced by the path
+    /// and {xattr:NAME} by the value of that extended attribute.
+    #[arg(short = 'F', long, value_name = "FMT")]
+    format: Option<String>,
+
+    /// Show individual access errors (default is just a summary).
     #[arg(short, long)]
     errors: bool,
+
+    /// Keep running, printing new matches as they appear.
+    #[arg(short, long)]
+    watch: bool,
+
+    /// With --watch, prefix each path with the kind of event.
+    #[arg(long, requires = "watch")]
+    events: bool,
+
+    /// Review the matches and choose ones to delete, move, or rename.
+    #[arg(long, conflicts_with_all = ["watch", "DB"])]
+    select: bool,
+
+    /// Reverse the moves and renames made by the last --select.
+    #[arg(long, conflicts_with_all = ["select", "watch", "DB"])]
+    undo: bool,
+
+    /// Check the paths listed on standard input (one per line) instead
+    /// of searching <BASE>.
+    #[arg(long, conflicts_with_all = ["watch", "select", "DB", "INDEX", "SNAP", "OLD"])]
+    stdin: bool,
+
+    /// With --stdin, the paths are separated by NULs instead of newlines.
+    #[arg(short = '0', long, requires = "stdin")]
+    null: bool,
+
+    /// Build (or refresh) an index of <BASE> in the file <DB>.
+    #[arg(long, name = "DB", conflicts_with_all = ["watch", "INDEX"])]
+    index_build: Option<PathBuf>,
+
+    /// Search the index in <INDEX> instead of walking the directory tree.
+    #[arg(long, name = "INDEX", conflicts_with = "watch")]
+    index: Option<PathBuf>,
+
+    /// Record the size, time, and permissions of each match in the
+    /// snapshot file <SNAP>.
+    #[arg(long, name = "SNAP", conflicts_with_all = ["watch", "select", "DB", "INDEX"])]
+    snapshot: Option<PathBuf>,
+
+    /// With --snapshot, also record a hash of each file's contents.
+    #[arg(long, requires = "SNAP")]
+    hash: bool,
+
+    /// Report matches added, removed, modified, or with changed
+    /// permissions since the snapshot in <OLD> was taken.
+    #[arg(long, name = "OLD", conflicts_with_all = ["watch", "select", "DB", "INDEX", "SNAP"])]
+    diff_snapshot: Option<PathBuf>,
+
+    /// Ignore the configuration files (and their defaults).
+    #[arg(long)]
+    no_config: bool,
 }
 
 /// Options derived from [`OptArgs`] to be usable to the rest of
 /// the program.
-#[derive(Default)]
 pub struct Opts {
-    /// Set of filename patterns against which to match.
-    pub patterns: RegexSet,
-    /// Base directory from which to start searching.
-    pub base: PathBuf,
-    /// Limit the search to this depth below the base directory.
-    pub depth: Option<usize>,
-    /// Whether to display aboslute (or relative) path names.
-    pub absolute: bool,
-    /// Whether to match on _any_ part of the path (not just
-    /// the final element).
-    pub full: bool,
-    /// Show errors (default is to ignore them because they are usually
-    /// just permissions errors).
+    /// The search itself.
+    pub finder: Finder,
+    /// Whether the search is fuzzy (and the results should be ranked).
+    pub fuzzy: bool,
+    /// Print at most this many (ranked) results.
+    pub limit: Option<usize>,
+    /// How to display matched paths.
+    pub paths: PathStyle,
+    /// Custom output format.
+    pub format: Option<Format>,
+    /// Show individual errors (default is to just summarize them, because
+    /// they are usually just permissions errors).
     pub errors: bool,
-    /// Directory entry types against which to match. Should default
-    /// to _all_ types if this is empty.
-    pub types: Vec<EType>,
-    /// Match only files with a modification time after this.
-    /// (Should default to no lower limit.)
-    pub mod_after: Option<SystemTime>,
-    /// Match only files with a modification time before this.
-    /// (Should default to no upper limit.)
-    pub mod_before: Option<SystemTime>,
+    /// Keep watching for new matches after the initial search.
+    pub watch: bool,
+    /// When watching, prefix each path with the type of event.
+    pub events: bool,
+    /// Let the user act on the matches instead of printing them.
+    pub select: bool,
+    /// Undo the last `select` instead of searching.
+    pub undo: bool,
+    /// Check paths from stdin instead of walking the directory tree.
+    pub stdin: bool,
+    /// The paths on stdin are separated by NULs.
+    pub null: bool,
+    /// Build an index of the base directory in this file (instead
+    /// of searching).
+    pub index_build: Option<PathBuf>,
+    /// Search this index instead of walking the directory tree.
+    pub index: Option<Index>,
+    /// Take a snapshot of the matches and save it in this file.
+    pub snapshot: Option<PathBuf>,
+    /// Hash file contents when taking a snapshot.
+    pub hash: bool,
+    /// Report changes since this snapshot.
+    pub diff_snapshot: Option<Snapshot>,
+}
+
+/**
+Parse the command line, filling in anything not specified there from the
+configuration files (see the [`config`](crate::config) module).
+
+If the first argument is `@NAME`, the options from the saved search `NAME`
+are used, too.
+*/
+fn parse_args() -> Result<OptArgs, String> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let search: Option<String> = args
+        .get(1)
+        .and_then(|a| a.to_str())
+        .and_then(|a| a.strip_prefix('@'))
+        .map(String::from);
+    if search.is_some() {
+        args.remove(1);
+    }
+
+    let cmd = OptArgs::command();
+    // This takes care of --help, --version, and errors on the command line
+    // itself before the configuration gets involved. An option the command
+    // line needs may come from the configuration, though, so that's only
+    // checked once everything is together.
+    let given = cmd
+        .clone()
+        .mut_args(|a| a.requires(Resettable::Reset))
+        .get_matches_from(&args);
+    let config = match given.get_flag("no_config") {
+        true => Config::default(),
+        false => Config::load(&cmd)?,
+    };
+    let mut full: Vec<OsString> = args[..1].to_vec();
+    full.extend(config.args(search.as_deref(), &given)?);
+    full.extend(args[1..].iter().cloned());
+
+    let matches = match cmd.clone().try_get_matches_from(full) {
+        Ok(matches) => matches,
+        // Only blame the configuration if the command line is fine
+        // on its own.
+        Err(e) => match cmd.try_get_matches_from(&args) {
+            Err(e) => e.exit(),
+            Ok(_) => {
+                return Err(format!(
+                    "with options from configuration files:\n{}",
+                    e.to_string().trim_end()
+                ))
+            }
+        },
+    };
+    OptArgs::from_arg_matches(&matches).map_err(|e| format!("{}", &e))
 }
 
 impl Opts {
     pub fn new() -> Result<Opts, String> {
-        let oa = OptArgs::parse();
-        if oa.pattern.is_empty() {
+        let oa = parse_args()?;
+        if oa.pattern.is_empty() && oa.index_build.is_none() && !oa.undo {
             return Err("you must specify at least one pattern".into());
         }
 
-        let mut opts = Opts::default();
+        let types = oa
+            .types
+            .iter()
+            .map(|s| EType::try_from(s.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let oa_strs: Vec<String> = if oa.regex {
-            oa.pattern
-        } else {
-            oa.pattern
-                .iter()
-                .map(|pat| Glob::new(pat))
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| format!("{}", &e))?
-                .into_iter()
-                .map(|g| String::from(g.regex()))
-                .collect()
+        let date_order = match oa.date_order.or_else(|| std::env::var(DATE_ORDER_VAR).ok()) {
+            Some(s) => DateOrder::try_from(s.as_str())?,
+            None => DateOrder::default(),
         };
 
-        let patterns = RegexSet::new(&oa_strs).map_err(|e| format!("{}", &e))?;
-        let types = oa
-            .types
+        let git_states = oa
+            .git
             .iter()
-            .map(|s| {
-                let r = EType::try_from(s.as_str());
-                r
-            })
+            .map(|s| GitState::try_from(s.as_str()))
             .collect::<Result<Vec<_>, _>>()?;
 
-        let mod_after = match oa.mod_after {
+        // When searching an index, the base directory is the one
+        // that was indexed.
+        let index = match oa.index {
+            Some(path) => Some(Index::read(&path).map_err(|e| format!("{}", &e))?),
             None => None,
-            Some(timestamp) => Some(times::parse_time(&timestamp)?),
         };
-        let mod_before = match oa.mod_before {
+        // Likewise when comparing with a snapshot.
+        let diff_snapshot = match oa.diff_snapshot {
+            Some(path) => Some(Snapshot::read(&path).map_err(|e| format!("{}", &e))?),
             None => None,
-            Some(timestamp) => Some(times::parse_time(&timestamp)?),
         };
-        if let (Some(a), Some(b)) = (mod_after, mod_before) {
-            if a >= b {
-                return Err(
-                    "--mod-after must be earlier than --mod-before to get any results".into(),
-                );
-            }
+        let base = match (&index, &diff_snapshot) {
+            (Some(index), _) => index.base().to_path_buf(),
+            (_, Some(snapshot)) => snapshot.base().to_path_buf(),
+            _ => PathBuf::from(oa.base),
+        };
+
+        let cwd = || {
+            display::current_dir()
+                .map_err(|e| format!("unable to determine current directory: {}", &e))
+        };
+        let paths = if oa.strip_base {
+            PathStyle::StripBase(base.clone())
+        } else if let Some(dir) = oa.relative_to {
+            let cwd = cwd()?;
+            let dir = display::normalize(&cwd.join(dir));
+            PathStyle::RelativeTo { cwd, dir }
+        } else if oa.absolute && oa.no_resolve {
+            PathStyle::Absolute { cwd: cwd()? }
+        } else if oa.absolute {
+            PathStyle::Canonical
+        } else {
+            PathStyle::AsFound
+        };
+
+        let mut search = Search::new(base)
+            .patterns(oa.pattern)
+            .regex(oa.regex)
+            .fuzzy(oa.fuzzy)
+            .full(oa.full)
+            .types(types)
+            .archives(oa.archives)
+            .prune(oa.prune);
+
+        for pat in oa.in_dir {
+            search = search.in_dir(pat);
+        }
+        for pat in oa.parent {
+            search = search.parent(pat);
+        }
+        for state in git_states {
+            search = search.git(state);
+        }
+        #[cfg(unix)]
+        for s in oa.xattr.iter() {
+            search = search.xattr(XattrPred::try_from(s.as_str())?);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            search = search.acl(oa.acl);
+        }
+        #[cfg(unix)]
+        if let Some(s) = oa.links {
+            search = search.links(LinkCount::try_from(s.as_str())?);
+        }
+        #[cfg(unix)]
+        if let Some(path) = oa.same_file {
+            search = search.same_file(path);
+        }
+        if let Some(depth) = oa.depth {
+            search = search.depth(depth);
         }
+        if let Some(timestamp) = oa.mod_after {
+            search = search.mod_after(times::parse_time_in_order(&timestamp, date_order)?);
+        }
+        if let Some(timestamp) = oa.mod_before {
+            search = search.mod_before(times::parse_time_in_order(&timestamp, date_order)?);
+        }
+
+        let ref_time = RefTime::try_from(oa.ref_time.as_str())?;
+        if let Some(path) = oa.newer {
+            search = search.mod_after(times::file_time(&path, ref_time)?);
+        }
+        if let Some(path) = oa.older {
+            search = search.mod_before(times::file_time(&path, ref_time)?);
+        }
+
+        let format = match oa.format {
+            Some(s) => Some(Format::try_from(s.as_str())?),
+            None => None,
+        };
+
+        let finder = search.build().map_err(|e| format!("{}", &e))?;
 
-        opts.patterns = patterns;
-        opts.base = PathBuf::from(oa.base);
-        opts.depth = oa.depth;
-        opts.absolute = oa.absolute;
-        opts.full = oa.full;
-        opts.errors = oa.errors;
-        opts.types = types;
-        opts.mod_after = mod_after;
-        opts.mod_before = mod_before;
-
-        Ok(opts)
+        Ok(Opts {
+            finder,
+            fuzzy: oa.fuzzy,
+            limit: oa.limit,
+            paths,
+            format,
+            errors: oa.errors,
+            watch: oa.watch,
+            events: oa.events,
+            select: oa.select,
+            undo: oa.undo,
+            stdin: oa.stdin,
+            null: oa.null,
+            index_build: oa.index_build,
+            index,
+            snapshot: oa.snapshot,
+            hash: oa.hash,
+            diff_snapshot,
+        })
     }
 }
@@ -0,0 +1,85 @@
+/*!
+Keeping track of how the run went, for the error summary and the
+exit code.
+*/
+use fine::FineErr;
+
+/// Exit code when at least one match was found (and nothing went wrong).
+pub const EXIT_MATCHES: i32 = 0;
+/// Exit code when the search completed but nothing matched.
+pub const EXIT_NO_MATCHES: i32 = 1;
+/// Exit code when errors were encountered (or the search couldn't be
+/// performed at all).
+pub const EXIT_ERRORS: i32 = 2;
+
+/// Tally of matches found and errors encountered.
+#[derive(Debug, Default)]
+pub struct Report {
+    matches: usize,
+    recorded: usize,
+    denied: usize,
+    not_found: usize,
+    loops: usize,
+    io: usize,
+}
+
+impl Report {
+    /// Note that a match has been found.
+    pub fn matched(&mut self) {
+        self.matches += 1;
+    }
+
+    /// Note the number of entries added to an index or a snapshot. (When
+    /// building one, these are the "results".)
+    pub fn recorded(&mut self, n: usize) {
+        self.recorded += n;
+    }
+
+    /// Tally an error, printing it if `show` is true.
+    pub fn error(&mut self, e: FineErr, show: bool) {
+        match e {
+            FineErr::PermissionDenied(_) => self.denied += 1,
+            FineErr::NotFound(_) => self.not_found += 1,
+            FineErr::Loop { .. } => self.loops += 1,
+            _ => self.io += 1,
+        }
+        if show {
+            eprintln!("{}", &e);
+        }
+    }
+
+    fn n_errors(&self) -> usize {
+        self.denied + self.not_found + self.loops + self.io
+    }
+
+    /// Print a summary of any errors encountered to stderr.
+    pub fn summarize(&self, shown: bool) {
+        let lines = [
+            (self.denied, "could not be read (permission denied)"),
+            (self.not_found, "disappeared during the search"),
+            (self.loops, "led to filesystem loops"),
+            (self.io, "caused other I/O errors"),
+        ];
+        for (n, what) in lines.iter() {
+            match n {
+                0 => {}
+                1 => eprintln!("1 path {}", what),
+                n => eprintln!("{} paths {}", n, what),
+            }
+        }
+        if !shown && self.n_errors() > 0 {
+            eprintln!("(use -e to show individual errors)");
+        }
+    }
+
+    /// The appropriate exit code for the way the run went.
+    pub fn exit_code(&self) -> i32 {
+        if self.n_errors() > 0 {
+            EXIT_ERRORS
+        } else if self.matches > 0 || self.recorded > 0 {
+            EXIT_MATCHES
+        } else {
+            EXIT_NO_MATCHES
+        }
+    }
+}
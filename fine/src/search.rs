@@ -0,0 +1,739 @@
+/*!
+Configuring and running searches.
+*/
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::{
+    fs::{FileType, Metadata},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use bstr::ByteSlice;
+use globset::Glob;
+use regex::bytes::RegexSet;
+use walkdir::WalkDir;
+
+use crate::{
+    fuzzy::Fuzzy,
+    git::{GitFilter, GitState},
+    types::{is_broken_link, EType, HasEType},
+    FineErr,
+};
+#[cfg(target_os = "linux")]
+use crate::types::has_acl;
+#[cfg(unix)]
+use crate::types::{LinkCount, XattrPred};
+
+/**
+Specification of a search.
+
+This is a builder; set the options you want and then call
+[`Search::build`] to get a [`Finder`] that can actually do the
+searching. Unless otherwise specified, it matches entries of all types,
+at all depths, with any modification time, comparing glob patterns
+against just the final element of each path.
+*/
+#[derive(Clone, Debug)]
+pub struct Search {
+    base: PathBuf,
+    patterns: Vec<String>,
+    regex: bool,
+    fuzzy: bool,
+    full: bool,
+    in_dir: Vec<String>,
+    parent: Vec<String>,
+    types: Vec<EType>,
+    depth: Option<usize>,
+    mod_after: Option<SystemTime>,
+    mod_before: Option<SystemTime>,
+    git: Vec<GitState>,
+    archives: bool,
+    prune: bool,
+    #[cfg(unix)]
+    xattrs: Vec<XattrPred>,
+    #[cfg(target_os = "linux")]
+    acl: bool,
+    #[cfg(unix)]
+    links: Option<LinkCount>,
+    #[cfg(unix)]
+    same_file: Option<PathBuf>,
+}
+
+impl Search {
+    /// Start specifying a search in and below the `base` directory.
+    pub fn new<P: Into<PathBuf>>(base: P) -> Search {
+        Search {
+            base: base.into(),
+            patterns: Vec::new(),
+            regex: false,
+            fuzzy: false,
+            full: false,
+            in_dir: Vec::new(),
+            parent: Vec::new(),
+            types: Vec::new(),
+            depth: None,
+            mod_after: None,
+            mod_before: None,
+            git: Vec::new(),
+            archives: false,
+            prune: false,
+            #[cfg(unix)]
+            xattrs: Vec::new(),
+            #[cfg(target_os = "linux")]
+            acl: false,
+            #[cfg(unix)]
+            links: None,
+            #[cfg(unix)]
+            same_file: None,
+        }
+    }
+
+    /// Add a pattern to match against. An entry matches if it matches
+    /// _any_ of the supplied patterns.
+    pub fn pattern<S: Into<String>>(mut self, pattern: S) -> Search {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Add several patterns to match against.
+    pub fn patterns<I, S>(mut self, patterns: I) -> Search
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.patterns.extend(patterns.into_iter().map(|p| p.into()));
+        self
+    }
+
+    /// Interpret patterns as regular expressions instead of globs.
+    pub fn regex(mut self, yes: bool) -> Search {
+        self.regex = yes;
+        self
+    }
+
+    /**
+    Treat patterns as fuzzy queries instead of globs: a name matches if
+    all the characters of a query appear in it in order. Use
+    [`Finder::score`] to rank the matches. (This overrides [`Search::regex`].)
+    */
+    pub fn fuzzy(mut self, yes: bool) -> Search {
+        self.fuzzy = yes;
+        self
+    }
+
+    /// Match patterns against the whole path instead of just the
+    /// final element.
+    pub fn full(mut self, yes: bool) -> Search {
+        self.full = yes;
+        self
+    }
+
+    /**
+    Match only entries inside a directory (below the base directory) whose
+    name matches `pattern`. If this is used more than once, entries inside
+    a directory matching _any_ of the patterns match.
+
+    Each directory name is matched separately, so (unlike with
+    [`Search::full`]) a pattern can't match across path separators.
+    Patterns are regular expressions if [`Search::regex`] is set, and
+    globs otherwise.
+    */
+    pub fn in_dir<S: Into<String>>(mut self, pattern: S) -> Search {
+        self.in_dir.push(pattern.into());
+        self
+    }
+
+    /// Match only entries whose immediate parent directory (which must be
+    /// below the base directory) has a name matching `pattern`; otherwise
+    /// like [`Search::in_dir`].
+    pub fn parent<S: Into<String>>(mut self, pattern: S) -> Search {
+        self.parent.push(pattern.into());
+        self
+    }
+
+    /// Add a type of entry to match. If no types are added, entries of
+    /// all types will match.
+    pub fn file_type(mut self, t: EType) -> Search {
+        self.types.push(t);
+        self
+    }
+
+    /// Add several types of entry to match.
+    pub fn types<I: IntoIterator<Item = EType>>(mut self, types: I) -> Search {
+        self.types.extend(types);
+        self
+    }
+
+    /// Limit the search to this depth below the base directory.
+    pub fn depth(mut self, depth: usize) -> Search {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Match only entries modified more recently than `t`.
+    pub fn mod_after(mut self, t: SystemTime) -> Search {
+        self.mod_after = Some(t);
+        self
+    }
+
+    /// Match only entries last modified before `t`.
+    pub fn mod_before(mut self, t: SystemTime) -> Search {
+        self.mod_before = Some(t);
+        self
+    }
+
+    /// Match only entries in this state in the git repository containing
+    /// the base directory. (Entries matching _any_ of the supplied states
+    /// match.)
+    pub fn git(mut self, state: GitState) -> Search {
+        self.git.push(state);
+        self
+    }
+
+    /// Match only entries whose extended attributes satisfy `pred`. (If
+    /// more than one is added, entries must satisfy _all_ of them.)
+    #[cfg(unix)]
+    pub fn xattr(mut self, pred: XattrPred) -> Search {
+        self.xattrs.push(pred);
+        self
+    }
+
+    /// Match only entries that have POSIX ACLs.
+    #[cfg(target_os = "linux")]
+    pub fn acl(mut self, yes: bool) -> Search {
+        self.acl = yes;
+        self
+    }
+
+    /// Match only entries with the given number of hard links.
+    #[cfg(unix)]
+    pub fn links(mut self, count: LinkCount) -> Search {
+        self.links = Some(count);
+        self
+    }
+
+    /// Match only entries that are the same file (that is, have the same
+    /// device and inode numbers) as the one at `path`.
+    #[cfg(unix)]
+    pub fn same_file<P: Into<PathBuf>>(mut self, path: P) -> Search {
+        self.same_file = Some(path.into());
+        self
+    }
+
+    /// Also search inside `.tar` (plain, gzipped, or zstd-compressed) and
+    /// `.zip` archives found during the walk. See the
+    /// [`archive`](crate::archive) module for details.
+    pub fn archives(mut self, yes: bool) -> Search {
+        self.archives = yes;
+        self
+    }
+
+    /// Don't look inside directories that match, so that only the
+    /// top-most of any nested matches are found.
+    pub fn prune(mut self, yes: bool) -> Search {
+        self.prune = yes;
+        self
+    }
+
+    /// Check the specification and compile the patterns.
+    pub fn build(self) -> Result<Finder, FineErr> {
+        let meta = std::fs::metadata(&self.base).map_err(|e| {
+            FineErr::Config(format!(
+                "unable to read from \"{}\": {}",
+                &self.base.display(),
+                &e
+            ))
+        })?;
+        if !meta.is_dir() {
+            return Err(FineErr::Config(format!(
+                "\"{}\" is not a directory",
+                &self.base.display()
+            )));
+        }
+
+        if let (Some(a), Some(b)) = (self.mod_after, self.mod_before) {
+            if a >= b {
+                return Err(FineErr::Config(
+                    "--mod-after must be earlier than --mod-before to get any results".into(),
+                ));
+            }
+        }
+
+        let (patterns, fuzzy) = if self.fuzzy {
+            (RegexSet::empty(), Some(Fuzzy::new(self.patterns)))
+        } else {
+            (compile(&self.patterns, self.regex)?, None)
+        };
+        let in_dir = if self.in_dir.is_empty() {
+            None
+        } else {
+            Some(compile(&self.in_dir, self.regex)?)
+        };
+        let parent = if self.parent.is_empty() {
+            None
+        } else {
+            Some(compile(&self.parent, self.regex)?)
+        };
+
+        #[cfg(unix)]
+        let same_file = match self.same_file {
+            Some(ref p) => {
+                let meta = std::fs::metadata(p).map_err(|e| {
+                    FineErr::Config(format!("unable to read \"{}\": {}", p.display(), &e))
+                })?;
+                Some((meta.dev(), meta.ino()))
+            }
+            None => None,
+        };
+
+        let git = if self.git.is_empty() {
+            None
+        } else {
+            Some(GitFilter::new(&self.base, &self.git)?)
+        };
+
+        Ok(Finder {
+            base: self.base,
+            patterns,
+            fuzzy,
+            full: self.full,
+            in_dir,
+            parent,
+            types: self.types,
+            depth: self.depth,
+            mod_after: self.mod_after,
+            mod_before: self.mod_before,
+            git,
+            archives: self.archives,
+            prune: self.prune,
+            #[cfg(unix)]
+            xattrs: self.xattrs,
+            #[cfg(target_os = "linux")]
+            acl: self.acl,
+            #[cfg(unix)]
+            links: self.links,
+            #[cfg(unix)]
+            same_file,
+        })
+    }
+}
+
+/// Compile `patterns` (regexes if `regex` is set, otherwise globs) into a
+/// single `RegexSet`.
+fn compile(patterns: &[String], regex: bool) -> Result<RegexSet, FineErr> {
+    let regexes = if regex {
+        patterns.to_vec()
+    } else {
+        patterns
+            .iter()
+            .map(|pat| Glob::new(pat).map(|g| String::from(g.regex())))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| FineErr::Config(format!("{}", &e)))?
+    };
+    RegexSet::new(&regexes).map_err(|e| FineErr::Config(format!("{}", &e)))
+}
+
+/// The `EType` of a `FileType`. (Every `FileType` is one of them on the
+/// supported platforms, so the fallback should never actually be needed.)
+fn etype_of(ft: &FileType) -> EType {
+    EType::of(ft).unwrap_or(EType::File)
+}
+
+/// A directory entry (or archive member) that matched a search.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    path: PathBuf,
+    file_type: EType,
+    depth: usize,
+    archived: bool,
+}
+
+impl Entry {
+    /// An entry for a member of an archive.
+    pub(crate) fn archived(path: PathBuf, file_type: EType, depth: usize) -> Entry {
+        Entry {
+            path,
+            file_type,
+            depth,
+            archived: true,
+        }
+    }
+
+    /// The path of the entry, starting with the search's base directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Consume the entry, returning its path.
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// The type of the entry. (Symbolic links are not followed.)
+    pub fn file_type(&self) -> EType {
+        self.file_type
+    }
+
+    /// Whether the entry is a member of an archive (rather than something
+    /// actually on disk).
+    pub fn in_archive(&self) -> bool {
+        self.archived
+    }
+
+    /// How far below the base directory the entry is.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Read the entry's metadata. (Symbolic links are not followed.) This
+    /// fails for entries inside archives.
+    pub fn metadata(&self) -> Result<Metadata, FineErr> {
+        self.path
+            .symlink_metadata()
+            .map_err(|e| FineErr::from_io(&self.path, e))
+    }
+}
+
+impl AsRef<Path> for Entry {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl From<walkdir::DirEntry> for Entry {
+    fn from(ent: walkdir::DirEntry) -> Self {
+        Entry {
+            file_type: etype_of(&ent.file_type()),
+            depth: ent.depth(),
+            path: ent.into_path(),
+            archived: false,
+        }
+    }
+}
+
+/// A compiled [`Search`], ready to look for matches.
+#[derive(Debug)]
+pub struct Finder {
+    base: PathBuf,
+    patterns: RegexSet,
+    fuzzy: Option<Fuzzy>,
+    full: bool,
+    in_dir: Option<RegexSet>,
+    parent: Option<RegexSet>,
+    types: Vec<EType>,
+    depth: Option<usize>,
+    mod_after: Option<SystemTime>,
+    mod_before: Option<SystemTime>,
+    git: Option<GitFilter>,
+    archives: bool,
+    prune: bool,
+    #[cfg(unix)]
+    xattrs: Vec<XattrPred>,
+    #[cfg(target_os = "linux")]
+    acl: bool,
+    #[cfg(unix)]
+    links: Option<LinkCount>,
+    /// Device and inode numbers of the file to match.
+    #[cfg(unix)]
+    same_file: Option<(u64, u64)>,
+}
+
+impl Finder {
+    /// The directory in which the search starts.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// The maximum depth below the base directory to search, if limited.
+    pub(crate) fn max_depth(&self) -> Option<usize> {
+        self.depth
+    }
+
+    /// Whether matching directories should not be searched.
+    pub(crate) fn prunes(&self) -> bool {
+        self.prune
+    }
+
+    /// Walk the directory tree, yielding matching entries (and any
+    /// errors encountered along the way).
+    pub fn iter(&self) -> Matches<'_> {
+        let mut walker = WalkDir::new(&self.base).follow_links(false);
+        if let Some(depth) = self.depth {
+            walker = walker.max_depth(depth);
+        }
+
+        Matches {
+            finder: self,
+            walker: walker.into_iter(),
+            members: Vec::new().into_iter(),
+        }
+    }
+
+    /**
+    Check a single path (which should be in or below the base directory)
+    against the search criteria, returning the corresponding `Entry` if
+    it matches.
+
+    This is for testing paths that don't come from walking the tree
+    (like ones that show up later while watching it).
+    */
+    pub fn check(&self, path: &Path) -> Result<Option<Entry>, FineErr> {
+        let depth = match path.strip_prefix(&self.base) {
+            Ok(rel) => rel.components().count(),
+            Err(_) => return Ok(None),
+        };
+        if let Some(max) = self.depth {
+            if depth > max {
+                return Ok(None);
+            }
+        }
+
+        self.check_at(path, depth)
+    }
+
+    /**
+    Check a path that may not be in the base directory at all (like one
+    from a list of paths supplied from elsewhere) against the search
+    criteria. The depth limit only applies to paths inside the base
+    directory; the `Entry` for any other path has a depth of its number
+    of components.
+    */
+    pub fn check_listed(&self, path: &Path) -> Result<Option<Entry>, FineErr> {
+        match path.strip_prefix(&self.base) {
+            Ok(_) => self.check(path),
+            Err(_) => self.check_at(path, path.components().count()),
+        }
+    }
+
+    fn check_at(&self, path: &Path, depth: usize) -> Result<Option<Entry>, FineErr> {
+        let meta = path
+            .symlink_metadata()
+            .map_err(|e| FineErr::from_io(path, e))?;
+        let file_type = meta.file_type();
+        let mtime = || meta.modified().map_err(|e| FineErr::from_io(path, e));
+        if self.filter(path, &file_type, mtime)? {
+            Ok(Some(Entry {
+                path: path.to_path_buf(),
+                file_type: etype_of(&file_type),
+                depth,
+                archived: false,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /**
+    Score how well a path matches the (fuzzy) patterns; higher scores are
+    better matches. Returns `None` if the path doesn't match, or if the
+    search isn't a fuzzy one.
+    */
+    pub fn score(&self, path: &Path) -> Option<i64> {
+        let fuzzy = self.fuzzy.as_ref()?;
+        if self.full {
+            fuzzy.score(path)
+        } else {
+            fuzzy.score(Path::new(path.file_name()?))
+        }
+    }
+
+    /// Deterime whether a given filename matches the supplied
+    /// set of patterns.
+    fn name_matches(&self, path: &Path) -> bool {
+        if self.fuzzy.is_some() {
+            return self.score(path).is_some();
+        }
+
+        let path_to_match = if self.full {
+            Some(path.as_os_str())
+        } else {
+            path.file_name()
+        };
+
+        match path_to_match.and_then(<[u8]>::from_os_str) {
+            Some(bytes) => self.patterns.is_match(bytes),
+            None => false,
+        }
+    }
+
+    /// Whether the search has criteria that can only be checked against
+    /// things actually on disk (and so can't match archive members).
+    pub(crate) fn disk_only(&self) -> bool {
+        #[cfg(unix)]
+        if !self.xattrs.is_empty() || self.links.is_some() || self.same_file.is_some() {
+            return true;
+        }
+        #[cfg(target_os = "linux")]
+        if self.acl {
+            return true;
+        }
+
+        self.git.is_some()
+    }
+
+    /// Determine whether the names of the directories containing `path`
+    /// (below the base directory) match the `in_dir` and `parent` patterns.
+    fn dirs_match(&self, path: &Path) -> bool {
+        if self.in_dir.is_none() && self.parent.is_none() {
+            return true;
+        }
+
+        // Paths outside the base directory (which can only be checked
+        // directly) have all their directories considered.
+        let rel = path.strip_prefix(&self.base).unwrap_or(path);
+        let dirs: Vec<&[u8]> = match rel.parent() {
+            Some(rel) => rel
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(name) => <[u8]>::from_os_str(name),
+                    _ => None,
+                })
+                .collect(),
+            None => return false,
+        };
+
+        if let Some(ref set) = self.in_dir {
+            if !dirs.iter().any(|d| set.is_match(d)) {
+                return false;
+            }
+        }
+        if let Some(ref set) = self.parent {
+            match dirs.last() {
+                Some(d) if set.is_match(d) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Determine whether an entry's modification time falls within the
+    /// bounds (if any) of the search.
+    fn time_matches(&self, modtime: SystemTime) -> bool {
+        if let Some(t) = self.mod_after {
+            if modtime <= t {
+                return false;
+            }
+        }
+        if let Some(t) = self.mod_before {
+            if modtime >= t {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Apply all the criteria to an entry. The modification time is only
+    /// fetched (by calling `modtime`) if it's needed.
+    pub(crate) fn filter<T, F>(&self, path: &Path, file_type: &T, modtime: F) -> Result<bool, FineErr>
+    where
+        T: HasEType,
+        F: FnOnce() -> Result<SystemTime, FineErr>,
+    {
+        if !self.types.is_empty() && !file_type.is_one(&self.types) {
+            // Whether a link is broken depends on more than its type.
+            let broken = self.types.contains(&EType::Broken)
+                && file_type.is(&EType::Link)
+                && is_broken_link(path);
+            if !broken {
+                return Ok(false);
+            }
+        }
+
+        let bounded = self.mod_after.is_some() || self.mod_before.is_some();
+        if bounded && !self.time_matches(modtime()?) {
+            return Ok(false);
+        }
+
+        if !self.name_matches(path) || !self.dirs_match(path) {
+            return Ok(false);
+        }
+
+        if let Some(ref git) = self.git {
+            if !git.matches(&self.base, path) {
+                return Ok(false);
+            }
+        }
+
+        #[cfg(unix)]
+        if self.links.is_some() || self.same_file.is_some() {
+            let meta = path
+                .symlink_metadata()
+                .map_err(|e| FineErr::from_io(path, e))?;
+            if let Some(count) = self.links {
+                if !count.matches(meta.nlink()) {
+                    return Ok(false);
+                }
+            }
+            if let Some(id) = self.same_file {
+                if (meta.dev(), meta.ino()) != id {
+                    return Ok(false);
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        for pred in self.xattrs.iter() {
+            if !pred.matches(path).map_err(|e| FineErr::from_io(path, e))? {
+                return Ok(false);
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.acl && !has_acl(path).map_err(|e| FineErr::from_io(path, e))? {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+/// Iterator over the matches of a [`Finder`]; returned by
+/// [`Finder::iter`].
+pub struct Matches<'a> {
+    finder: &'a Finder,
+    walker: walkdir::IntoIter,
+    /// Matching members of the last archive encountered, waiting to be
+    /// returned.
+    members: std::vec::IntoIter<Result<Entry, FineErr>>,
+}
+
+impl<'a> Iterator for Matches<'a> {
+    type Item = Result<Entry, FineErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(res) = self.members.next() {
+                return Some(res);
+            }
+
+            let ent = match self.walker.next()? {
+                Ok(ent) => ent,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            if self.finder.archives && ent.file_type().is_file() {
+                if let Some(members) = self.finder.search_archive(ent.path(), ent.depth()) {
+                    self.members = members.into_iter();
+                }
+            }
+
+            let res = self.finder.filter(ent.path(), &ent.file_type(), || {
+                let meta = ent.metadata()?;
+                meta.modified().map_err(|e| FineErr::from_io(ent.path(), e))
+            });
+            match res {
+                Ok(true) => {
+                    if self.finder.prune && ent.file_type().is_dir() {
+                        self.walker.skip_current_dir();
+                    }
+                    return Some(Ok(Entry::from(ent)));
+                }
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,484 @@
+/*!
+Interactively deleting, moving, and renaming matches.
+
+With `--select`, the matches are presented for review instead of being
+printed: in a buffer opened in `$VISUAL` (or `$EDITOR`), if either is set,
+or as a numbered list with a prompt otherwise. Entries can be marked to be
+deleted, moved into a directory, or renamed, and the resulting plan is
+shown for confirmation before anything is touched.
+
+Moves and renames are done first. If any of them fails, the ones already
+done are put back, so either all of them happen or none of them do. They
+are also recorded in an undo log, which `--undo` plays backward (anything
+it can't put back stays in the log). Deletions happen last, and can't be
+undone.
+*/
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use bstr::ByteSlice;
+use fine::{Entry, FineErr};
+
+use crate::{config, display, opt::Opts, report::Report};
+
+/// Name of the undo log (in the user's state directory).
+static UNDO_FILE: &str = "fine-undo.log";
+/// First line of the undo log.
+static UNDO_HEADER: &str = "# fine undo log: original path, new path";
+
+static BUFFER_HELP: &str = r##"# Change "keep" to an action for each entry you want to change:
+#
+#   delete N        delete the entry (and everything in it, if it's a directory)
+#   move N DIR      move the entry into the directory DIR
+#   rename N PATH   rename the entry; PATH is relative to the current directory,
+#                   or just a new name to leave it in the same directory
+#
+# Actions can be shortened to their first letters. Lines starting with "#"
+# (or removed entirely) are ignored. You'll see the whole plan before
+# anything happens.
+"##;
+
+static PROMPT_HELP: &str = r#"Mark entries with "delete N", "move N DIR", or "rename N PATH" (or just d, m, r);
+"keep N" unmarks one, "list" shows them again, "done" moves on, and "quit" quits."#;
+
+/// What to do with an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Action {
+    Keep,
+    Delete,
+    Move(PathBuf),
+    Rename(PathBuf),
+}
+
+/// A single change, ready to be applied.
+#[derive(Clone, Debug)]
+enum Step {
+    Delete(PathBuf),
+    Move { from: PathBuf, to: PathBuf },
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+fn undo_path() -> Result<PathBuf, String> {
+    config::user_dir("XDG_STATE_HOME", ".local/state")
+        .map(|dir| dir.join(UNDO_FILE))
+        .ok_or_else(|| "unable to determine where to keep the undo log".to_string())
+}
+
+/// The form of `path` shown to the user.
+fn show(path: &Path) -> String {
+    <[u8]>::from_path(path)
+        .map(|b| b.to_str_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Write a line of the undo log, with the paths exactly as they are.
+fn write_log_line<W: Write>(log: &mut W, from: &Path, to: &Path) -> io::Result<()> {
+    log.write_all(<[u8]>::from_path(from).unwrap_or_default())?;
+    log.write_all(b"\t")?;
+    log.write_all(<[u8]>::from_path(to).unwrap_or_default())?;
+    log.write_all(b"\n")
+}
+
+/// Start a new undo log at `path`.
+fn create_log(path: &Path) -> io::Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut log = File::create(path)?;
+    writeln!(log, "{}", UNDO_HEADER)?;
+    Ok(log)
+}
+
+/// Parse an action line, like `delete 3` or `move 2 ../old`. Returns
+/// `Ok(None)` for blank lines and comments.
+fn parse_line(line: &str, n_paths: usize) -> Result<Option<(usize, Action)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut chunks = line.splitn(3, char::is_whitespace);
+    let verb = chunks.next().unwrap_or_default();
+    let n = chunks.next().unwrap_or_default();
+    let arg = chunks.next().unwrap_or_default().trim();
+
+    let n: usize = match n.parse() {
+        Ok(n) if n >= 1 && n <= n_paths => n,
+        _ => return Err(format!("\"{}\": {} is not an entry number", line, n)),
+    };
+    let action = match (verb.to_ascii_lowercase().as_str(), arg) {
+        ("k" | "keep", _) => Action::Keep,
+        ("d" | "delete", _) => Action::Delete,
+        ("m" | "move" | "r" | "rename", "") => {
+            return Err(format!("\"{}\": where to?", line));
+        }
+        ("m" | "move", arg) => Action::Move(PathBuf::from(arg)),
+        ("r" | "rename", arg) => Action::Rename(PathBuf::from(arg)),
+        _ => return Err(format!("\"{}\": unknown action \"{}\"", line, verb)),
+    };
+
+    Ok(Some((n - 1, action)))
+}
+
+/// Have the user mark entries in a buffer opened in `editor`.
+fn mark_in_editor(editor: &str, paths: &[PathBuf]) -> Result<Vec<Action>, Box<dyn Error>> {
+    let buffer = std::env::temp_dir().join(format!("fine-select-{}.txt", std::process::id()));
+    {
+        // Don't follow (or reuse) anything already there.
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&buffer)
+            .map_err(|e| format!("unable to create \"{}\": {}", buffer.display(), &e))?;
+        f.write_all(BUFFER_HELP.as_bytes())?;
+        for (n, path) in paths.iter().enumerate() {
+            writeln!(f, "keep {} {}", n + 1, show(path))?;
+        }
+    }
+
+    let mut words = editor.split_whitespace();
+    let prog = words.next().unwrap_or_default();
+    let status = Command::new(prog).args(words).arg(&buffer).status();
+    let text = fs::read_to_string(&buffer);
+    let _ = fs::remove_file(&buffer);
+    match status {
+        Ok(st) if st.success() => {}
+        Ok(st) => return Err(format!("editor \"{}\" failed ({})", editor, st).into()),
+        Err(e) => return Err(format!("unable to run editor \"{}\": {}", editor, &e).into()),
+    }
+
+    let mut actions = vec![Action::Keep; paths.len()];
+    for line in text?.lines() {
+        if let Some((n, action)) = parse_line(line, paths.len())? {
+            actions[n] = action;
+        }
+    }
+
+    Ok(actions)
+}
+
+fn list(paths: &[PathBuf], actions: &[Action]) {
+    let width = paths.len().to_string().len();
+    for (n, (path, action)) in paths.iter().zip(actions.iter()).enumerate() {
+        let mark = match action {
+            Action::Keep => "",
+            Action::Delete => "  [delete]",
+            Action::Move(_) => "  [move]",
+            Action::Rename(_) => "  [rename]",
+        };
+        eprintln!("{:>w$}  {}{}", n + 1, show(path), mark, w = width);
+    }
+}
+
+/// Have the user mark entries by typing commands at a prompt. Returns
+/// `None` if the user quits.
+fn mark_at_prompt(paths: &[PathBuf]) -> Result<Option<Vec<Action>>, Box<dyn Error>> {
+    let mut actions = vec![Action::Keep; paths.len()];
+    list(paths, &actions);
+    eprintln!("{}", PROMPT_HELP);
+
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        eprint!("> ");
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(None);
+        }
+        match line.trim() {
+            "l" | "list" => list(paths, &actions),
+            "done" => return Ok(Some(actions)),
+            "q" | "quit" => return Ok(None),
+            _ => match parse_line(&line, paths.len()) {
+                Ok(Some((n, action))) => actions[n] = action,
+                Ok(None) => {}
+                Err(e) => eprintln!("{}", &e),
+            },
+        }
+    }
+}
+
+/// Turn the user's marks into the changes to actually make, checking
+/// that they can all be made.
+fn plan(paths: &[PathBuf], actions: &[Action]) -> Result<Vec<Step>, String> {
+    let cwd = display::current_dir()
+        .map_err(|e| format!("unable to determine current directory: {}", &e))?;
+    let abs = |p: &Path| display::normalize(&cwd.join(p));
+
+    let mut renames: Vec<Step> = Vec::new();
+    let mut deletes: Vec<Step> = Vec::new();
+    let mut sources: Vec<PathBuf> = Vec::new();
+    let mut dests: HashSet<PathBuf> = HashSet::new();
+
+    for (path, action) in paths.iter().zip(actions.iter()) {
+        let to = match action {
+            Action::Keep => continue,
+            Action::Delete => None,
+            Action::Move(dir) => {
+                if !dir.is_dir() {
+                    return Err(format!("\"{}\" is not a directory", dir.display()));
+                }
+                // Paths from a walk always have a final component.
+                Some(dir.join(path.file_name().unwrap_or_default()))
+            }
+            Action::Rename(new) if new.components().count() == 1 => Some(path.with_file_name(new)),
+            Action::Rename(new) => Some(new.clone()),
+        };
+
+        for p in Some(path).into_iter().chain(to.iter()) {
+            let b = <[u8]>::from_path(p).unwrap_or_default();
+            if b.contains(&b'\n') || b.contains(&b'\t') {
+                return Err(format!("can't handle \"{}\" (tabs or newlines)", p.display()));
+            }
+        }
+        if path.symlink_metadata().is_err() {
+            return Err(format!("\"{}\" no longer exists", path.display()));
+        }
+        sources.push(abs(path));
+
+        match to {
+            None => deletes.push(Step::Delete(path.clone())),
+            Some(to) => {
+                if to.symlink_metadata().is_ok() || !dests.insert(abs(&to)) {
+                    return Err(format!(
+                        "can't move \"{}\" to \"{}\": something is already there",
+                        path.display(),
+                        to.display()
+                    ));
+                }
+                let from = path.clone();
+                match action {
+                    Action::Move(_) => renames.push(Step::Move { from, to }),
+                    _ => renames.push(Step::Rename { from, to }),
+                }
+            }
+        }
+    }
+
+    // Changing something inside a directory that is itself changing would
+    // depend on the order in which things happen.
+    for a in sources.iter() {
+        if let Some(b) = sources.iter().find(|b| a != *b && a.starts_with(b)) {
+            return Err(format!(
+                "\"{}\" is inside \"{}\", which is also being changed",
+                a.display(),
+                b.display()
+            ));
+        }
+    }
+
+    renames.extend(deletes);
+    Ok(renames)
+}
+
+fn print_plan(steps: &[Step]) {
+    for step in steps.iter() {
+        match step {
+            Step::Delete(path) => eprintln!("delete  {}", show(path)),
+            Step::Move { from, to } => eprintln!("move    {} -> {}", show(from), show(to)),
+            Step::Rename { from, to } => eprintln!("rename  {} -> {}", show(from), show(to)),
+        }
+    }
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Reverse the moves and renames in `done` (which are in the order they
+/// were made).
+fn put_back(done: &[(&Path, &Path)], report: &mut Report) {
+    for (from, to) in done.iter().rev() {
+        if let Err(e) = fs::rename(to, from) {
+            report.error(FineErr::from_io(*to, e), true);
+        }
+    }
+}
+
+/// Carry out the plan, recording the moves and renames in the undo log.
+fn apply(steps: &[Step], report: &mut Report, show_errors: bool) -> Result<(), Box<dyn Error>> {
+    let cwd = display::current_dir()?;
+    let abs = |p: &Path| display::normalize(&cwd.join(p));
+
+    // A plan that only deletes things leaves the last undo log alone.
+    let log_path = undo_path()?;
+    let mut log = match steps.iter().any(|s| !matches!(s, Step::Delete(_))) {
+        true => Some(create_log(&log_path)?),
+        false => None,
+    };
+
+    let mut done: Vec<(&Path, &Path)> = Vec::new();
+    for step in steps.iter() {
+        let (from, to) = match step {
+            Step::Move { from, to } | Step::Rename { from, to } => (from, to),
+            Step::Delete(_) => continue,
+        };
+        // Something may have turned up there while the user was deciding,
+        // and renaming over it would quietly replace it.
+        let moved = match to.symlink_metadata() {
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "something is already there",
+            )),
+            Err(_) => fs::rename(from, to),
+        };
+        let res = match moved {
+            Ok(_) => {
+                done.push((from, to));
+                match log {
+                    Some(ref mut log) => write_log_line(log, &abs(from), &abs(to)).map_err(|e| {
+                        format!("unable to write to \"{}\": {}", log_path.display(), &e)
+                    }),
+                    None => Ok(()),
+                }
+            }
+            Err(e) => Err(format!(
+                "unable to move \"{}\" to \"{}\": {}",
+                from.display(),
+                to.display(),
+                &e
+            )),
+        };
+        if let Err(msg) = res {
+            put_back(&done, report);
+            drop(log);
+            let _ = fs::remove_file(&log_path);
+            return Err(format!("{}\nnothing was changed", msg).into());
+        }
+    }
+
+    for step in steps.iter() {
+        if let Step::Delete(path) = step {
+            let res = match path.symlink_metadata() {
+                Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+                Ok(_) => fs::remove_file(path),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                report.error(FineErr::from_io(path, e), show_errors);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect the matches, let the user decide what to do with them, and
+/// do it.
+pub fn select(opts: &Opts, report: &mut Report) -> Result<(), Box<dyn Error>> {
+    let results: Box<dyn Iterator<Item = Result<PathBuf, FineErr>>> = match opts.index {
+        Some(ref index) => Box::new(opts.finder.search_index(index)),
+        // Archive members can't be changed without changing the archive.
+        None => Box::new(
+            opts.finder
+                .iter()
+                .filter(|res| !matches!(res, Ok(ent) if ent.in_archive()))
+                .map(|res| res.map(Entry::into_path)),
+        ),
+    };
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for res in results {
+        match res {
+            Ok(path) => {
+                report.matched();
+                paths.push(path);
+            }
+            Err(e) => report.error(e, opts.errors),
+        }
+    }
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty());
+    let actions = match editor {
+        Some(editor) => mark_in_editor(&editor, &paths)?,
+        None => match mark_at_prompt(&paths)? {
+            Some(actions) => actions,
+            None => return Ok(()),
+        },
+    };
+
+    let steps = plan(&paths, &actions)?;
+    if steps.is_empty() {
+        eprintln!("nothing to do");
+        return Ok(());
+    }
+    print_plan(&steps);
+    if confirm("Go ahead?")? {
+        apply(&steps, report, opts.errors)?;
+    } else {
+        eprintln!("nothing was changed");
+    }
+
+    Ok(())
+}
+
+/// Reverse the moves and renames from the last `--select`.
+pub fn undo(opts: &Opts, report: &mut Report) -> Result<(), Box<dyn Error>> {
+    let log_path = undo_path()?;
+    let text = match fs::read(&log_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err("nothing to undo".into());
+        }
+        Err(e) => return Err(format!("unable to read \"{}\": {}", log_path.display(), &e).into()),
+    };
+
+    let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for line in ByteSlice::lines(text.as_slice()).skip(1) {
+        let (from, to) = line
+            .split_once_str("\t")
+            .and_then(|(a, b)| Some((a.to_path().ok()?, b.to_path().ok()?)))
+            .ok_or_else(|| format!("\"{}\" is damaged", log_path.display()))?;
+        moves.push((from.to_path_buf(), to.to_path_buf()));
+    }
+
+    let mut failed: Vec<&(PathBuf, PathBuf)> = Vec::new();
+    for step in moves.iter().rev() {
+        let (from, to) = step;
+        if from.symlink_metadata().is_ok() {
+            let e = io::Error::new(io::ErrorKind::AlreadyExists, "something is already there");
+            report.error(FineErr::from_io(from, e), opts.errors);
+            failed.push(step);
+            continue;
+        }
+        match fs::rename(to, from) {
+            Ok(_) => report.matched(),
+            Err(e) => {
+                report.error(FineErr::from_io(to, e), opts.errors);
+                failed.push(step);
+            }
+        }
+    }
+
+    // Keep whatever couldn't be undone, so it can be tried again.
+    if failed.is_empty() {
+        fs::remove_file(&log_path)?;
+    } else {
+        let mut log = create_log(&log_path)?;
+        for (from, to) in failed.iter().rev() {
+            write_log_line(&mut log, from, to)?;
+        }
+        eprintln!(
+            "{} move(s) couldn't be undone; they're still in \"{}\"",
+            failed.len(),
+            log_path.display()
+        );
+    }
+
+    Ok(())
+}
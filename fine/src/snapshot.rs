@@ -0,0 +1,392 @@
+/*!
+Snapshots of the entries in a tree, for finding out later what changed.
+
+A snapshot records the type, size, modification time, and permissions of
+each entry that matches a search, and (optionally) a hash of the contents
+of each regular file. Comparing an old snapshot with a new one of the same
+tree reports the entries that have been added, removed, modified, or had
+their permissions changed.
+
+## Format
+
+Integers, byte strings, and times are encoded the same way as in an
+[`Index`](crate::Index).
+
+```text
+magic:     b"FINESNP\x02"
+base:      byte string (canonical path of the base directory)
+hashed:    one byte (1 if file contents were hashed, 0 if not)
+records:   (until the end of the file, sorted by path)
+    path:      byte string (relative to the base)
+    type:      one byte (the same letters as in an index)
+    size:      integer
+    mtime:     signed seconds, nanoseconds
+    mode:      integer (permission bits)
+    hash:      byte string (empty if not hashed or not a regular file)
+```
+*/
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::{
+    cmp::Ordering,
+    fs::{File, Metadata},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bstr::ByteSlice;
+
+use crate::{
+    index::{write_bytes, write_etype, write_time, write_uint, Reader},
+    types::EType,
+    Entry, Finder, FineErr,
+};
+
+static MAGIC: &[u8] = b"FINESNP\x02";
+
+/// What's recorded about each entry.
+#[derive(Clone, Debug)]
+struct Record {
+    /// Path relative to the snapshot's base.
+    path: PathBuf,
+    etype: EType,
+    size: u64,
+    mtime: SystemTime,
+    mode: u32,
+    hash: Vec<u8>,
+}
+
+impl Record {
+    /// Whether the entry's contents (as far as we can tell) are different
+    /// in `other`.
+    fn modified(&self, other: &Record) -> bool {
+        let hashed = !self.hash.is_empty() && !other.hash.is_empty();
+        self.etype != other.etype
+            || self.size != other.size
+            || self.mtime != other.mtime
+            || (hashed && self.hash != other.hash)
+    }
+}
+
+/// The ways an entry can change between snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// The entry's type, size, modification time, or contents changed.
+    Modified,
+    /// The entry's permissions changed.
+    Mode,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Mode => "mode",
+        }
+    }
+}
+
+/// A difference between two snapshots.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// The path of the entry (starting with the snapshot's base).
+    pub path: PathBuf,
+}
+
+/// A record of the entries in a tree at some point in time.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    base: PathBuf,
+    hashed: bool,
+    records: Vec<Record>,
+}
+
+fn bad_snapshot(path: &Path) -> FineErr {
+    FineErr::Config(format!(
+        "\"{}\" is not a valid snapshot file",
+        path.display()
+    ))
+}
+
+#[cfg(unix)]
+fn mode_of(meta: &Metadata) -> u32 {
+    meta.permissions().mode() & 0o7777
+}
+
+/// Read-only is the only permission we know about on other platforms.
+#[cfg(not(unix))]
+fn mode_of(meta: &Metadata) -> u32 {
+    if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().as_bytes().to_vec())
+}
+
+/// Make the record of `ent` for a snapshot of `base`.
+fn record(base: &Path, ent: &Entry, hash: bool) -> Result<Record, FineErr> {
+    let path = ent.path();
+    let meta = ent.metadata()?;
+    let mtime = meta.modified().map_err(|e| FineErr::from_io(path, e))?;
+    let hash = if hash && meta.is_file() {
+        hash_file(path).map_err(|e| FineErr::from_io(path, e))?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Record {
+        path: path.strip_prefix(base).unwrap_or(path).to_path_buf(),
+        etype: ent.file_type(),
+        size: meta.len(),
+        mtime,
+        mode: mode_of(&meta),
+        hash,
+    })
+}
+
+impl Finder {
+    /**
+    Take a snapshot of the entries that match the search (not including
+    any archive members), hashing the contents of regular files if `hash`
+    is set. Errors reading individual entries are passed to `on_error`,
+    and the offending entries left out.
+    */
+    pub fn snapshot<F>(&self, hash: bool, mut on_error: F) -> Result<Snapshot, FineErr>
+    where
+        F: FnMut(FineErr),
+    {
+        let base = self.base().canonicalize().map_err(|e| {
+            FineErr::Config(format!(
+                "unable to read from \"{}\": {}",
+                self.base().display(),
+                &e
+            ))
+        })?;
+
+        let mut records = Vec::new();
+        for res in self.iter() {
+            match res {
+                Ok(ent) if ent.in_archive() => {}
+                Ok(ent) => match record(self.base(), &ent, hash) {
+                    Ok(rec) => records.push(rec),
+                    Err(e) => on_error(e),
+                },
+                Err(e) => on_error(e),
+            }
+        }
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Snapshot {
+            base,
+            hashed: hash,
+            records,
+        })
+    }
+}
+
+impl Snapshot {
+    /// Read a snapshot from the file at `path`.
+    pub fn read(path: &Path) -> Result<Snapshot, FineErr> {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .map_err(|e| FineErr::from_io(path, e))?;
+
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| bad_snapshot(path))?;
+        let mut r = Reader { bytes: rest };
+        let base = r
+            .bytes()
+            .and_then(|b| b.to_path().ok())
+            .ok_or_else(|| bad_snapshot(path))?
+            .to_path_buf();
+        let hashed = r.byte().ok_or_else(|| bad_snapshot(path))? != 0;
+
+        let mut records = Vec::new();
+        while !r.bytes.is_empty() {
+            let rec = Snapshot::read_record(&mut r).ok_or_else(|| bad_snapshot(path))?;
+            records.push(rec);
+        }
+
+        Ok(Snapshot {
+            base,
+            hashed,
+            records,
+        })
+    }
+
+    fn read_record(r: &mut Reader) -> Option<Record> {
+        let path = r.bytes()?.to_path().ok()?.to_path_buf();
+        let etype = r.etype()?;
+        let size = r.uint()?;
+        let mtime = r.time()?;
+        let mode = u32::try_from(r.uint()?).ok()?;
+        let hash = r.bytes()?.to_vec();
+        Some(Record {
+            path,
+            etype,
+            size,
+            mtime,
+            mode,
+            hash,
+        })
+    }
+
+    /// Write the snapshot to the file at `path`. (Like an index, it's
+    /// written to a temporary file and moved into place.)
+    pub fn write(&self, path: &Path) -> Result<(), FineErr> {
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp = PathBuf::from(tmp_name);
+
+        self.write_to(&tmp).map_err(|e| FineErr::from_io(&tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| FineErr::from_io(path, e))
+    }
+
+    fn write_to(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        write_bytes(&mut w, <[u8]>::from_path(&self.base).unwrap_or_default())?;
+        w.write_all(&[self.hashed as u8])?;
+
+        for rec in self.records.iter() {
+            write_bytes(&mut w, <[u8]>::from_path(&rec.path).unwrap_or_default())?;
+            write_etype(&mut w, rec.etype)?;
+            write_uint(&mut w, rec.size)?;
+            write_time(&mut w, rec.mtime)?;
+            write_uint(&mut w, rec.mode as u64)?;
+            write_bytes(&mut w, &rec.hash)?;
+        }
+
+        w.flush()
+    }
+
+    /// The (canonical) path of the directory the snapshot was taken of.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Whether the contents of files were hashed.
+    pub fn hashed(&self) -> bool {
+        self.hashed
+    }
+
+    /// The number of entries in the snapshot.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /**
+    Compare this snapshot with a `newer` one of the same tree, returning
+    the changes in order of path. (An entry whose contents and permissions
+    both changed appears twice.) File contents are only compared if both
+    snapshots hashed them.
+    */
+    pub fn diff(&self, newer: &Snapshot) -> Vec<Change> {
+        // Joining an empty path would add a trailing slash to the base.
+        let change = |kind: ChangeKind, rec: &Record| Change {
+            kind,
+            path: match rec.path.as_os_str().is_empty() {
+                true => newer.base.clone(),
+                false => newer.base.join(&rec.path),
+            },
+        };
+
+        let mut changes = Vec::new();
+        let mut old = self.records.iter().peekable();
+        let mut new = newer.records.iter().peekable();
+        loop {
+            let order = match (old.peek(), new.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(o), Some(n)) => o.path.cmp(&n.path),
+            };
+            match order {
+                Ordering::Less => {
+                    if let Some(o) = old.next() {
+                        changes.push(change(ChangeKind::Removed, o));
+                    }
+                }
+                Ordering::Greater => {
+                    if let Some(n) = new.next() {
+                        changes.push(change(ChangeKind::Added, n));
+                    }
+                }
+                Ordering::Equal => {
+                    if let (Some(o), Some(n)) = (old.next(), new.next()) {
+                        if o.modified(n) {
+                            changes.push(change(ChangeKind::Modified, n));
+                        }
+                        if o.mode != n.mode {
+                            changes.push(change(ChangeKind::Mode, n));
+                        }
+                    }
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enum_iterator::all;
+
+    #[test]
+    fn round_trip() {
+        let records: Vec<Record> = all::<EType>()
+            .enumerate()
+            .map(|(n, etype)| Record {
+                path: PathBuf::from(format!("entry{}", n)),
+                etype,
+                size: n as u64 * 1000,
+                mtime: SystemTime::now(),
+                mode: 0o640,
+                hash: vec![n as u8; 4],
+            })
+            .collect();
+        let snap = Snapshot {
+            base: PathBuf::from("/some/where"),
+            hashed: true,
+            records,
+        };
+
+        let file = std::env::temp_dir().join(format!("fine-snapshot-test-{}", std::process::id()));
+        snap.write(&file).unwrap();
+        let read = Snapshot::read(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(read.base(), snap.base());
+        assert!(read.hashed());
+        assert_eq!(read.len(), snap.len());
+        for (a, b) in snap.records.iter().zip(read.records.iter()) {
+            assert_eq!(a.path, b.path);
+            assert_eq!(a.etype, b.etype);
+            assert_eq!(a.size, b.size);
+            assert_eq!(a.mtime, b.mtime);
+            assert_eq!(a.mode, b.mode);
+            assert_eq!(a.hash, b.hash);
+        }
+        assert!(snap.diff(&read).is_empty());
+    }
+}
@@ -1,80 +1,453 @@
 /*!
 For filtering by time.
 */
-use std::time::SystemTime;
+use std::{
+    convert::TryFrom,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
 
-use time::{Date, format_description::FormatItem, macros::format_description, OffsetDateTime, Time, UtcOffset};
+use time::{Date, format_description::FormatItem, macros::format_description, Month, OffsetDateTime, Time, UtcOffset};
 
-static ERR_MSG: &str = r#"illegal date/time format; try one of:
+static MDY_ERR_MSG: &str = r#"illegal date/time format; try one of:
     "2021-01-27 7:20:35"
     "1/27/2021 7:20:35"
     2021-01-27
     1/27/2021
+    "Jan 27 2021"
+    "Jan 27"
     7:20:35
     7:20
 "#;
 
-/*
-The various supported date/time formats to try when parsing.
-*/
-const ISO_DATE: &[FormatItem] = format_description!(
-    "[year]-[month padding:none]-[day padding:none]"
-);
-const USA_DATE: &[FormatItem] = format_description!(
-    "[month padding:none]/[day padding:none]/[year]"
-);
+static DMY_ERR_MSG: &str = r#"illegal date/time format; try one of:
+    "2021-01-27 7:20:35"
+    "27/1/2021 7:20:35"
+    2021-01-27
+    27/1/2021
+    27.1.2021
+    "27 Jan 2021"
+    "27 Jan"
+    7:20:35
+    7:20
+"#;
+
+static YMD_ERR_MSG: &str = r#"illegal date/time format; try one of:
+    "2021-01-27 7:20:35"
+    "2021/1/27 7:20:35"
+    2021-01-27
+    2021/1/27
+    "2021 Jan 27"
+    "Jan 27"
+    7:20:35
+    7:20
+"#;
+
+static MONTHS: &[(&str, Month)] = &[
+    ("january", Month::January),
+    ("february", Month::February),
+    ("march", Month::March),
+    ("april", Month::April),
+    ("may", Month::May),
+    ("june", Month::June),
+    ("july", Month::July),
+    ("august", Month::August),
+    ("september", Month::September),
+    ("october", Month::October),
+    ("november", Month::November),
+    ("december", Month::December),
+];
+
 const TIME: &[FormatItem] = format_description!(
     "[hour padding:none]:[minute][optional [:[second]]]"
 );
 
-fn try_date(datestr: &str) -> Result<Date, &'static str> {
-    if let Ok(d) = Date::parse(datestr, ISO_DATE) {
-        return Ok(d)
+/**
+The order in which the day, month, and year appear in numeric dates
+like `1/7/2021`.
+
+ISO-style dates (`2021-01-07`) and dates with month names
+(`7 Jan 2021`) are unambiguous, and are accepted regardless.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DateOrder {
+    /// day/month/year, like most of the world
+    Dmy,
+    /// month/day/year, like North America
+    Mdy,
+    /// year/month/day, like East Asia
+    Ymd,
+}
+
+impl DateOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DateOrder::Dmy => "dmy",
+            DateOrder::Mdy => "mdy",
+            DateOrder::Ymd => "ymd",
+        }
+    }
+
+    /// The error message listing the formats acceptable in this order.
+    fn err_msg(&self) -> &'static str {
+        match self {
+            DateOrder::Dmy => DMY_ERR_MSG,
+            DateOrder::Mdy => MDY_ERR_MSG,
+            DateOrder::Ymd => YMD_ERR_MSG,
+        }
+    }
+}
+
+/// Month/day/year is the default, for backward compatibility.
+impl Default for DateOrder {
+    fn default() -> Self {
+        DateOrder::Mdy
+    }
+}
+
+/// The `TryFrom` impl is used in parsing user input.
+impl TryFrom<&str> for DateOrder {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmy" => Ok(DateOrder::Dmy),
+            "mdy" => Ok(DateOrder::Mdy),
+            "ymd" => Ok(DateOrder::Ymd),
+            _ => Err(format!(
+                "date order {} invalid\npossible values are: dmy, mdy, ymd",
+                s
+            )),
+        }
+    }
+}
+
+/**
+Which of a reference file's timestamps to use as a bound with
+`--newer` and `--older`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefTime {
+    /// last modification time
+    Modified,
+    /// last access time
+    Accessed,
+    /// last status change time (when the metadata was last changed)
+    #[cfg(unix)]
+    Changed,
+}
+
+impl RefTime {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefTime::Modified => "mtime",
+            RefTime::Accessed => "atime",
+            #[cfg(unix)]
+            RefTime::Changed => "ctime",
+        }
+    }
+}
+
+impl Default for RefTime {
+    fn default() -> Self {
+        RefTime::Modified
+    }
+}
+
+/// The `TryFrom` impl is used in parsing user input.
+impl TryFrom<&str> for RefTime {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "m" | "mtime" | "mod" | "modified" => Ok(RefTime::Modified),
+            "a" | "atime" | "access" | "accessed" => Ok(RefTime::Accessed),
+            #[cfg(unix)]
+            "c" | "ctime" | "change" | "changed" => Ok(RefTime::Changed),
+            _ => {
+                #[cfg(unix)]
+                let allowed = "mtime, atime, ctime";
+                #[cfg(not(unix))]
+                let allowed = "mtime, atime";
+                Err(format!(
+                    "reference time {} invalid or not supported on this platform\npossible values are: {}",
+                    s, allowed
+                ))
+            }
+        }
+    }
+}
+
+/**
+Get the `which` timestamp of the file at `path` (following symbolic
+links), for use as a bound on modification times.
+*/
+pub fn file_time(path: &Path, which: RefTime) -> Result<SystemTime, String> {
+    let err = |e: std::io::Error| {
+        format!(
+            "unable to read {} of reference file \"{}\": {}",
+            which.as_str(),
+            path.display(),
+            &e
+        )
+    };
+    let meta = std::fs::metadata(path).map_err(err)?;
+
+    match which {
+        RefTime::Modified => meta.modified().map_err(err),
+        RefTime::Accessed => meta.accessed().map_err(err),
+        #[cfg(unix)]
+        RefTime::Changed => Ok(from_unix(meta.ctime(), meta.ctime_nsec() as u32)),
+    }
+}
+
+/// Convert a Unix-style timestamp (seconds since the epoch, which may be
+/// negative, plus nanoseconds) to a `SystemTime`.
+pub(crate) fn from_unix(secs: i64, nanos: u32) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
     } else {
-        Date::parse(datestr, USA_DATE).map_err(|_| ERR_MSG)
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// Convert a `SystemTime` to a Unix-style timestamp; the inverse of
+/// [`from_unix`].
+pub(crate) fn to_unix(t: SystemTime) -> (i64, u32) {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            let secs = -(d.as_secs() as i64);
+            match d.subsec_nanos() {
+                0 => (secs, 0),
+                n => (secs - 1, 1_000_000_000 - n),
+            }
+        }
+    }
+}
+
+/// Find the month whose name starts with `s` (which must be at least
+/// three letters long).
+fn month_named(s: &str) -> Option<Month> {
+    if s.len() < 3 {
+        return None;
+    }
+    let s = s.to_ascii_lowercase();
+    MONTHS
+        .iter()
+        .find(|(name, _)| name.starts_with(s.as_str()))
+        .map(|(_, m)| *m)
+}
+
+/// Parse a year, which must be written out in full.
+fn parse_year(s: &str) -> Option<i32> {
+    if s.len() < 4 {
+        return None;
     }
+    s.parse().ok()
 }
 
-fn try_time(timestr: &str) -> Result<Time, &'static str> {
-    Time::parse(timestr, TIME).map_err(|_| ERR_MSG)
+/// Parse dates with month names, like "27 Jan 2021", "Jan 27, 2021",
+/// or just "Jan 27" (which is taken to be in `this_year`).
+fn try_named_date(datestr: &str, this_year: i32) -> Option<Date> {
+    let mut month: Option<Month> = None;
+    let mut nums: Vec<&str> = Vec::new();
+    for tok in datestr
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+    {
+        if tok.chars().all(|c| c.is_ascii_digit()) {
+            nums.push(tok);
+        } else if month.is_none() {
+            month = Some(month_named(tok.trim_end_matches('.'))?);
+        } else {
+            return None;
+        }
+    }
+    let month = month?;
+
+    let (day, year) = match &nums[..] {
+        [day] => (day.parse().ok()?, this_year),
+        [a, b] if a.len() >= 4 => (b.parse().ok()?, parse_year(a)?),
+        [day, year] => (day.parse().ok()?, parse_year(year)?),
+        _ => return None,
+    };
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+/// Parse all-numeric dates, like "2021-01-27" or "27/1/2021", using
+/// `order` to resolve the ambiguous ones.
+fn try_numeric_date(datestr: &str, order: DateOrder) -> Option<Date> {
+    let sep = ['-', '/', '.']
+        .iter()
+        .copied()
+        .find(|&c| datestr.contains(c))?;
+    let parts: Vec<&str> = datestr.split(sep).collect();
+    let (a, b, c) = match &parts[..] {
+        [a, b, c] => (*a, *b, *c),
+        _ => return None,
+    };
+
+    // A leading four-digit year is unambiguous; this also covers the
+    // ISO format. Otherwise, dashes are only allowed in ISO dates.
+    let (y, m, d) = if a.len() >= 4 {
+        (a, b, c)
+    } else if sep == '-' {
+        return None;
+    } else {
+        match order {
+            DateOrder::Dmy => (c, b, a),
+            DateOrder::Mdy => (c, a, b),
+            DateOrder::Ymd => (a, b, c),
+        }
+    };
+
+    let year = parse_year(y)?;
+    let month = Month::try_from(m.parse::<u8>().ok()?).ok()?;
+    let day = d.parse().ok()?;
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+fn try_date(datestr: &str, order: DateOrder, this_year: i32) -> Result<Date, &'static str> {
+    try_numeric_date(datestr, order)
+        .or_else(|| try_named_date(datestr, this_year))
+        .ok_or_else(|| order.err_msg())
+}
+
+fn try_time(timestr: &str, order: DateOrder) -> Result<Time, &'static str> {
+    Time::parse(timestr, TIME).map_err(|_| order.err_msg())
+}
+
+/**
+Attempt to parse a string into an `OffsetDateTime`, interpreting
+numeric dates in month/day/year order.
+
+See [`parse_time_in_order`] for the details.
+*/
+pub fn parse_time(tstr: &str) -> Result<SystemTime, &'static str> {
+    parse_time_in_order(tstr, DateOrder::default())
 }
 
 /**
 Attempt to parse a string into an `OffsetDateTime`.
 
 The user should be able to enter the timestamp in a number of
-mutually-unambiguous formats (see `ERR_MSG`, above). This essentially
+formats (see the `*_ERR_MSG`s, above); which of the ambiguous all-numeric
+date formats is accepted depends on `order`. This essentially
 tries all of them until one works, or it gives up and returns an error.
 
 Dates with no times default to midnight (the earliest time in the given
-day); times with no dates default to the current day; seconds are
-optional (defaults to 0).
+day); times with no dates default to the current day; dates with no
+years default to the current year; seconds are optional (defaults to 0).
 */
-pub fn parse_time(tstr: &str) -> Result<SystemTime, &'static str> {
+pub fn parse_time_in_order(tstr: &str, order: DateOrder) -> Result<SystemTime, &'static str> {
     // If we can't determine the timezone, we just pretend it's UTC.
     let tz_offs = UtcOffset::current_local_offset()
         .unwrap_or(UtcOffset::UTC);
+    let now = OffsetDateTime::from(SystemTime::now()).to_offset(tz_offs);
 
-    let chunks: Vec<&str> = tstr.split_ascii_whitespace().collect();
-    
-    let tstamp = match &chunks[..] {
-        [datestr, timestr] => {
-            let d = try_date(datestr)?;
-            let t = try_time(timestr)?;
+    let mut chunks: Vec<&str> = tstr.split_ascii_whitespace().collect();
+    // The time, if present, is always last, and is the only part
+    // with colons in it.
+    let timestr = match chunks.last() {
+        Some(s) if s.contains(':') => chunks.pop(),
+        Some(_) => None,
+        None => return Err(order.err_msg()),
+    };
+    let datestr = chunks.join(" ");
+
+    let tstamp = match (datestr.as_str(), timestr) {
+        ("", Some(timestr)) => {
+            let t = try_time(timestr, order)?;
+            now.replace_time(t)
+        }
+        (datestr, Some(timestr)) => {
+            let d = try_date(datestr, order, now.year())?;
+            let t = try_time(timestr, order)?;
             d.with_time(t).assume_offset(tz_offs)
         }
-        [onestr] => {
-            if let Ok(d) = try_date(onestr) {
-                // This unwrap should be okay; midnight is in range.
-                d.with_hms(0, 0, 0).unwrap().assume_offset(tz_offs)
-            } else {
-                let t = try_time(onestr)?;
-                let today_time: OffsetDateTime = SystemTime::now().into();
-                today_time.replace_time(t).replace_offset(tz_offs)
-            }
-        },
-        _ => return Err(ERR_MSG),
+        (datestr, None) => {
+            let d = try_date(datestr, order, now.year())?;
+            // This unwrap should be okay; midnight is in range.
+            d.with_hms(0, 0, 0).unwrap().assume_offset(tz_offs)
+        }
     };
 
     Ok(tstamp.into())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn numeric_orders() {
+        let d = date!(2021 - 01 - 07);
+        assert_eq!(try_date("1/7/2021", DateOrder::Mdy, 2000), Ok(d));
+        assert_eq!(try_date("7/1/2021", DateOrder::Dmy, 2000), Ok(d));
+        assert_eq!(try_date("7.1.2021", DateOrder::Dmy, 2000), Ok(d));
+        assert_eq!(try_date("2021/1/7", DateOrder::Ymd, 2000), Ok(d));
+        // A leading year is unambiguous in any order.
+        assert_eq!(try_date("2021/1/7", DateOrder::Mdy, 2000), Ok(d));
+        assert_eq!(try_date("2021/1/7", DateOrder::Dmy, 2000), Ok(d));
+    }
+
+    #[test]
+    fn iso_in_any_order() {
+        for order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+            assert_eq!(try_date("2021-01-27", order, 2000), Ok(date!(2021 - 01 - 27)));
+            // Dashes are only for ISO dates.
+            assert!(try_date("01-27-2021", order, 2000).is_err());
+        }
+    }
+
+    #[test]
+    fn impossible_in_order() {
+        assert!(try_date("27/1/2021", DateOrder::Mdy, 2000).is_err());
+        assert!(try_date("1/27/2021", DateOrder::Dmy, 2000).is_err());
+        // Two-digit years aren't allowed.
+        assert!(try_date("1/27/21", DateOrder::Mdy, 2000).is_err());
+    }
+
+    #[test]
+    fn named_months() {
+        let d = date!(2021 - 01 - 27);
+        for order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+            assert_eq!(try_date("Jan 27 2021", order, 2000), Ok(d));
+            assert_eq!(try_date("27 January 2021", order, 2000), Ok(d));
+            assert_eq!(try_date("Jan. 27, 2021", order, 2000), Ok(d));
+            assert_eq!(try_date("2021 Jan 27", order, 2000), Ok(d));
+            assert_eq!(try_date("jan 27", order, 2021), Ok(d));
+        }
+        assert!(try_date("Ja 27 2021", DateOrder::Mdy, 2000).is_err());
+    }
+
+    #[test]
+    fn error_lists_formats_for_order() {
+        assert_eq!(parse_time_in_order("nonsense", DateOrder::Dmy), Err(DMY_ERR_MSG));
+        assert_eq!(parse_time_in_order("nonsense", DateOrder::Ymd), Err(YMD_ERR_MSG));
+        assert_eq!(parse_time("nonsense"), Err(MDY_ERR_MSG));
+    }
+
+    #[test]
+    fn date_order_names() {
+        for order in [DateOrder::Dmy, DateOrder::Mdy, DateOrder::Ymd] {
+            assert_eq!(DateOrder::try_from(order.as_str()), Ok(order));
+        }
+        assert_eq!(DateOrder::try_from("DMY"), Ok(DateOrder::Dmy));
+        assert!(DateOrder::try_from("myd").is_err());
+    }
+
+    #[test]
+    fn unix_round_trip() {
+        for &(secs, nanos) in &[(0, 0), (1_600_000_000, 5), (-1, 0), (-1, 999_999_999), (-86_400, 1)] {
+            assert_eq!(to_unix(from_unix(secs, nanos)), (secs, nanos));
+        }
+    }
+}
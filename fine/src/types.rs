@@ -1,5 +1,6 @@
 /*!
-Machinery for filtering by entry type.
+Machinery for filtering by entry type (and other platform-specific
+properties, like extended attributes).
 
 This module is an absolute _mess_ of #[cfg(...)] directives, but I can't
 think of a better way to do this. I'd like to thank `rustc` for always
@@ -8,18 +9,23 @@ having my back.
 use std::{
     convert::TryFrom,
     fs::FileType,
+    path::Path,
 };
 #[cfg(unix)]
-use std::os::unix::fs::FileTypeExt;
+use std::{
+    ffi::OsString,
+    io,
+    os::unix::fs::FileTypeExt,
+};
 #[cfg(windows)]
 use std::os::windows::fs::FileTypeExt;
-#[cfg(wasi)]
+#[cfg(target_os = "wasi")]
 use std::os::wasi::fs::FileTypeExt;
 
 use enum_iterator::{all, Sequence};
 
 /// Type of directory entry.
-#[derive(Clone, Copy, Debug, Sequence)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Sequence)]
 #[non_exhaustive]
 pub enum EType {
     /// regular file
@@ -28,21 +34,30 @@ pub enum EType {
     Dir,
     /// symbolic link
     Link,
+    /// symbolic link whose target doesn't exist (this can't be determined
+    /// from a `FileType` alone, so a `FileType` is never `Broken`; see
+    /// [`is_broken_link`])
+    Broken,
     /// FIFO (i.e., a pipe)
     #[cfg(unix)]
     Fifo,
     /// a socket
-    #[cfg(any(unix, wasi))]
+    #[cfg(any(unix, target_os = "wasi"))]
     Socket,
     /// block device
-    #[cfg(any(unix, wasi))]
+    #[cfg(any(unix, target_os = "wasi"))]
     Block,
     /// character device
-    #[cfg(any(unix, wasi))]
+    #[cfg(any(unix, target_os = "wasi"))]
     Char,
 }
 
 impl EType {
+    /// Determine which `EType` (if any) a `FileType` is.
+    pub fn of(ft: &FileType) -> Option<EType> {
+        all::<EType>().find(|t| ft.is(t))
+    }
+
     pub fn as_str(&self) -> &'static str {
         use EType::*;
 
@@ -50,13 +65,14 @@ impl EType {
             File => "file",
             Dir => "dir",
             Link => "link",
+            Broken => "broken",
             #[cfg(unix)]
             Fifo => "fifo",
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             Socket => "socket",
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             Block => "block",
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             Char => "char",
         }
     }
@@ -73,13 +89,14 @@ impl TryFrom<&str> for EType {
             "f" | "file" => Ok(File),
             "d" | "dir" | "directory" => Ok(Dir),
             "l" | "link" | "symlink" => Ok(Link),
+            "broken" | "dangling" => Ok(Broken),
             #[cfg(unix)]
             "p" | "pipe" | "fifo" => Ok(Fifo),
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             "s" | "sock" | "socket" => Ok(Socket),
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             "b" | "block" => Ok(Block),
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             "c" | "ch" | "char" | "character" => Ok(Char),
             _ => {
                 let allowed_types: Vec<&str> = all::<EType>().map(|t| t.as_str()).collect();
@@ -96,7 +113,7 @@ impl TryFrom<&str> for EType {
 /// This trait is purely to make checking whether a given file's
 /// [`FileType`](std::fs::FileType) is in the collection of filteree-for
 /// file types.
-pub(crate) trait HasEType {
+pub trait HasEType {
     /// Return whether the receiver is of the provided `EType`.
     fn is(&self, entry_type: &EType) -> bool;
 
@@ -121,16 +138,204 @@ impl HasEType for FileType {
             File => self.is_file(),
             Dir => self.is_dir(),
             Link => self.is_symlink(),
+            Broken => false,
             #[cfg(unix)]
             Fifo => self.is_fifo(),
             #[cfg(unix)]
             Socket => self.is_socket(),
-            #[cfg(wasi)]
+            #[cfg(target_os = "wasi")]
             Socket => self.is_socket_dgram() || self.is_socket_stream(),
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             Block => self.is_block_device(),
-            #[cfg(any(unix, wasi))]
+            #[cfg(any(unix, target_os = "wasi"))]
             Char => self.is_char_device(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// An `EType` is, trivially, itself.
+impl HasEType for EType {
+    fn is(&self, t: &EType) -> bool {
+        self == t
+    }
+}
+
+/// Determine whether the entry at `path` is a symbolic link whose target
+/// doesn't exist (or otherwise can't be reached).
+pub fn is_broken_link(path: &Path) -> bool {
+    match path.symlink_metadata() {
+        Ok(meta) if meta.file_type().is_symlink() => path.metadata().is_err(),
+        _ => false,
+    }
+}
+
+/**
+A requirement on the number of hard links to an entry.
+*/
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkCount {
+    Exactly(u64),
+    MoreThan(u64),
+    FewerThan(u64),
+}
+
+#[cfg(unix)]
+impl LinkCount {
+    /// Determine whether `n` links satisfy the requirement.
+    pub fn matches(&self, n: u64) -> bool {
+        match *self {
+            LinkCount::Exactly(m) => n == m,
+            LinkCount::MoreThan(m) => n > m,
+            LinkCount::FewerThan(m) => n < m,
+        }
+    }
+}
+
+/// The `TryFrom` impl is used in parsing user input of the form
+/// `N`, `>N`, or `<N`.
+#[cfg(unix)]
+impl TryFrom<&str> for LinkCount {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let t = s.trim();
+        let (f, n): (fn(u64) -> LinkCount, &str) = if let Some(n) = t.strip_prefix('>') {
+            (LinkCount::MoreThan, n)
+        } else if let Some(n) = t.strip_prefix('<') {
+            (LinkCount::FewerThan, n)
+        } else {
+            (LinkCount::Exactly, t)
+        };
+
+        match n.trim().parse::<u64>() {
+            Ok(n) => Ok(f(n)),
+            Err(_) => Err(format!(
+                "link count {} invalid; try something like 2, >1, or <3",
+                s
+            )),
+        }
+    }
+}
+
+/**
+A requirement that an entry have a given extended attribute (and maybe
+that it have a specific value).
+
+Extended attributes are only supported on Unix-like systems (and not
+even all of those).
+*/
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct XattrPred {
+    name: OsString,
+    value: Option<Vec<u8>>,
+}
+
+#[cfg(unix)]
+impl XattrPred {
+    /// Require that an entry have the attribute `name`.
+    pub fn new<S: Into<OsString>>(name: S) -> XattrPred {
+        XattrPred {
+            name: name.into(),
+            value: None,
+        }
+    }
+
+    /// Require that an entry have the attribute `name`, set to `value`.
+    pub fn with_value<S: Into<OsString>, V: Into<Vec<u8>>>(name: S, value: V) -> XattrPred {
+        XattrPred {
+            name: name.into(),
+            value: Some(value.into()),
+        }
+    }
+
+    /// Determine whether the entry at `path` satisfies the predicate.
+    /// (Symbolic links are not followed.)
+    pub fn matches(&self, path: &Path) -> io::Result<bool> {
+        match (get_xattr(path, &self.name)?, &self.value) {
+            (None, _) => Ok(false),
+            (Some(_), None) => Ok(true),
+            (Some(v), Some(want)) => Ok(&v == want),
+        }
+    }
+}
+
+/// The `TryFrom` impl is used in parsing user input of the form
+/// `NAME` or `NAME=VALUE`.
+#[cfg(unix)]
+impl TryFrom<&str> for XattrPred {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let pred = match s.split_once('=') {
+            Some((name, value)) => XattrPred::with_value(name, value),
+            None => XattrPred::new(s),
+        };
+        if pred.name.is_empty() {
+            return Err(format!("\"{}\" doesn't specify an attribute name", s));
+        }
+        Ok(pred)
+    }
+}
+
+/**
+Get the value of the extended attribute `name` of the entry at `path`,
+or `None` if it doesn't have one (or the filesystem doesn't support
+them). Symbolic links are not followed.
+
+Some attributes (like SELinux labels) are stored with a trailing NUL;
+that's removed.
+*/
+#[cfg(unix)]
+pub fn get_xattr(path: &Path, name: &std::ffi::OsStr) -> io::Result<Option<Vec<u8>>> {
+    match xattr::get(path, name) {
+        Ok(Some(mut v)) => {
+            if v.last() == Some(&0) {
+                v.pop();
+            }
+            Ok(Some(v))
+        }
+        Ok(None) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Determine whether the entry at `path` has a POSIX ACL (beyond what's
+/// expressed by its ordinary permission bits).
+#[cfg(target_os = "linux")]
+pub fn has_acl(path: &Path) -> io::Result<bool> {
+    for name in ["system.posix_acl_access", "system.posix_acl_default"].iter() {
+        if get_xattr(path, name.as_ref())?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_count_parse() {
+        assert_eq!(LinkCount::try_from("2"), Ok(LinkCount::Exactly(2)));
+        assert_eq!(LinkCount::try_from(">1"), Ok(LinkCount::MoreThan(1)));
+        assert_eq!(LinkCount::try_from("<3"), Ok(LinkCount::FewerThan(3)));
+        assert_eq!(LinkCount::try_from(" > 1 "), Ok(LinkCount::MoreThan(1)));
+        for bad in ["", ">", "two", "-1", ">=2", "1.5"] {
+            assert!(LinkCount::try_from(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn link_count_matches() {
+        assert!(LinkCount::Exactly(2).matches(2));
+        assert!(!LinkCount::Exactly(2).matches(1));
+        assert!(LinkCount::MoreThan(1).matches(2));
+        assert!(!LinkCount::MoreThan(1).matches(1));
+        assert!(LinkCount::FewerThan(3).matches(2));
+        assert!(!LinkCount::FewerThan(3).matches(3));
+    }
+}
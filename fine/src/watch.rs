@@ -0,0 +1,195 @@
+/*!
+Watching the search tree for new matches.
+
+After the initial search, `--watch` keeps running and reports entries
+that come to match as they're created, modified, or renamed into the
+tree. (On Linux this is driven by inotify; other platforms use whatever
+the `notify` crate recommends.)
+*/
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, stdout, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Sender},
+    time::{Duration, Instant},
+};
+
+use fine::FineErr;
+use notify::{
+    event::{EventKind, ModifyKind, RenameMode},
+    Event, RecursiveMode, Watcher,
+};
+use walkdir::WalkDir;
+
+use crate::{opt::Opts, print_path, report::Report};
+
+/// A modification this soon after a path was reported as created is
+/// taken to be part of its creation, and not reported again.
+const COALESCE: Duration = Duration::from_secs(1);
+
+/// Messages received by the main watch loop.
+enum Msg {
+    Fs(notify::Result<Event>),
+    Quit,
+}
+
+/// The kinds of events that can cause an entry to be reported.
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Created,
+    Modified,
+    Renamed,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Created => "created",
+            Kind::Modified => "modified",
+            Kind::Renamed => "renamed",
+        }
+    }
+
+    /// Determine which (if any) reportable kind of event this is.
+    fn from_event(kind: &EventKind) -> Option<Kind> {
+        match kind {
+            EventKind::Create(_) => Some(Kind::Created),
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => None,
+            EventKind::Modify(ModifyKind::Name(_)) => Some(Kind::Renamed),
+            EventKind::Modify(_) => Some(Kind::Modified),
+            _ => None,
+        }
+    }
+}
+
+/// Print `path` (preceded by the event type, if requested) if it
+/// matches the search. Returns whether it was printed.
+fn check_and_print(opts: &Opts, report: &mut Report, path: &Path, kind: Kind) -> bool {
+    match opts.finder.check(path) {
+        Ok(Some(ent)) => {
+            report.matched();
+            if opts.events {
+                let mut stdout = stdout();
+                stdout.write_all(kind.as_str().as_bytes()).unwrap();
+                stdout.write_all(b"\t").unwrap();
+            }
+            print_path(opts, ent.path());
+            return true;
+        }
+        Ok(None) => {}
+        // The entry may have disappeared again before we got to look at
+        // it; that isn't worth complaining about.
+        Err(FineErr::NotFound(_)) => {}
+        Err(e) => report.error(e, opts.errors),
+    }
+    false
+}
+
+/// Turn an error from the watcher into one the [`Report`] can tally.
+fn watch_err(base: &Path, e: notify::Error) -> FineErr {
+    let path = e.paths.first().map_or_else(|| base.to_path_buf(), PathBuf::clone);
+    match e.kind {
+        notify::ErrorKind::Io(ioe) => FineErr::from_io(path, ioe),
+        kind => {
+            let msg = format!("{}", notify::Error::new(kind));
+            FineErr::Io(path, io::Error::new(io::ErrorKind::Other, msg))
+        }
+    }
+}
+
+/// Handle a single filesystem event.
+///
+/// The watcher reports absolute paths, so `root` (the absolute version of
+/// the base directory) is used to turn them back into the form `walkdir` would
+/// produce, so they look the same as the initial matches. `created` holds
+/// the paths recently reported as created, and when.
+fn handle_event(
+    opts: &Opts,
+    report: &mut Report,
+    root: &Path,
+    created: &mut HashMap<PathBuf, Instant>,
+    evt: Event,
+) {
+    let kind = match Kind::from_event(&evt.kind) {
+        Some(kind) => kind,
+        None => return,
+    };
+    // When both ends of a rename are reported together, the destination
+    // is last, and that's the only one we care about.
+    let path: PathBuf = match evt.paths.last() {
+        Some(path) => match path.strip_prefix(root) {
+            Ok(rel) => opts.finder.base().join(rel),
+            Err(_) => path.clone(),
+        },
+        None => return,
+    };
+
+    // Creating a file usually means writing to it right away, too.
+    let now = Instant::now();
+    created.retain(|_, &mut when| now.duration_since(when) < COALESCE);
+    if let (Kind::Modified, true) = (kind, created.contains_key(&path)) {
+        return;
+    }
+    let path = path.as_path();
+
+    if check_and_print(opts, report, path, kind) {
+        if let Kind::Created = kind {
+            created.insert(path.to_path_buf(), now);
+        }
+    }
+
+    // A directory moved into the tree won't generate events for any of
+    // its contents, so we need to go looking for them.
+    if let Kind::Renamed = kind {
+        if path.is_dir() {
+            for ent in WalkDir::new(path).min_depth(1).follow_links(false) {
+                match ent {
+                    Ok(ent) => {
+                        check_and_print(opts, report, ent.path(), kind);
+                    }
+                    Err(e) => report.error(e.into(), opts.errors),
+                }
+            }
+        }
+    }
+}
+
+/**
+Perform the initial search, then continue watching the base directory for new
+matches until interrupted.
+*/
+pub fn watch(opts: &Opts, report: &mut Report) -> Result<(), Box<dyn Error>> {
+    let base = opts.finder.base();
+    let root = std::env::current_dir()?.join(base);
+    let (tx, rx) = channel::<Msg>();
+
+    let fs_tx: Sender<Msg> = tx.clone();
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = fs_tx.send(Msg::Fs(res));
+    })
+    .map_err(|e| format!("unable to start watching: {}", &e))?;
+
+    ctrlc::set_handler(move || {
+        let _ = tx.send(Msg::Quit);
+    })
+    .map_err(|e| format!("unable to set interrupt handler: {}", &e))?;
+
+    // Start watching before the initial walk so nothing that shows up
+    // in the meantime gets missed.
+    watcher
+        .watch(base, RecursiveMode::Recursive)
+        .map_err(|e| format!("unable to watch \"{}\": {}", &base.display(), &e))?;
+    crate::walk_and_check(opts, report);
+
+    let mut created: HashMap<PathBuf, Instant> = HashMap::new();
+    while let Ok(msg) = rx.recv() {
+        match msg {
+            Msg::Fs(Ok(evt)) => handle_event(opts, report, &root, &mut created, evt),
+            Msg::Fs(Err(e)) => report.error(watch_err(base, e), opts.errors),
+            Msg::Quit => break,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+/*!
+`--git` should work the same on paths found by walking the tree and on
+paths listed on stdin.
+*/
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// Make a repository in which `src/a.rs` is tracked and `src/b.rs` isn't.
+fn repo(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fine-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/a.rs"), b"a").unwrap();
+    fs::write(dir.join("src/b.rs"), b"b").unwrap();
+    for args in [&["init", "-q"][..], &["add", "src/a.rs"][..]] {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    dir
+}
+
+fn fine(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fine"))
+        .args(args)
+        .current_dir(dir)
+        // Keep any configuration files out of it.
+        .env("HOME", dir)
+        .env_remove("XDG_CONFIG_HOME")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    if let Some(input) = input {
+        child.stdin.take().unwrap().write_all(input).unwrap();
+    }
+    drop(child.stdin.take());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn tracked_walk() {
+    let dir = repo("walk");
+    let out = fine(&dir, &["-g", "tracked", "-t", "file", "*.rs"], None);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(out.status.success());
+    let out = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(out.lines().collect::<Vec<_>>(), ["./src/a.rs"]);
+}
+
+#[test]
+fn tracked_stdin() {
+    let dir = repo("stdin");
+    let out = fine(
+        &dir,
+        &["--stdin", "-g", "tracked", "*.rs"],
+        Some(b"src/a.rs\nsrc/b.rs\n"),
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "src/a.rs\n");
+}
+
+#[test]
+fn tracked_stdin_null() {
+    let dir = repo("null");
+    let out = fine(
+        &dir,
+        &["--stdin", "-0", "-g", "tracked", "*.rs"],
+        Some(b"src/a.rs\0src/b.rs\0"),
+    );
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "src/a.rs\n");
+}
@@ -0,0 +1,174 @@
+/*!
+Restricting edits to some of the chunks of the input.
+
+A chunk is only edited if it satisfies every kind of address given:
+
+  * it's in one of the `--lines` ranges (counting from 1 in each input),
+  * it matches every `--where` pattern and none of the `--where-not` ones,
+  * it's in one of the `--between` ranges, which start with a chunk that
+    matches `START` and end with the next one after that that matches
+    `END` (both included).
+
+Chunks that aren't addressed are passed through unaltered (or, when
+extracting, left out).
+*/
+use std::convert::TryFrom;
+
+use regex::bytes::Regex;
+
+/// A range of chunk numbers (counting from 1), like `10-20`, `10-`,
+/// `-20`, or just `10`.
+#[derive(Clone, Copy, Debug)]
+pub struct LineRange {
+    first: usize,
+    last: usize,
+}
+
+impl LineRange {
+    fn contains(&self, n: usize) -> bool {
+        self.first <= n && n <= self.last
+    }
+}
+
+impl TryFrom<&str> for LineRange {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let bad = || {
+            format!(
+                "invalid line range \"{}\"; try something like 10-20, 10-, -20, or 10",
+                s
+            )
+        };
+        let num = |t: &str| -> Result<usize, String> {
+            match t.trim().parse::<usize>() {
+                Ok(0) | Err(_) => Err(bad()),
+                Ok(n) => Ok(n),
+            }
+        };
+
+        let (first, last) = match s.split_once('-') {
+            None => {
+                let n = num(s)?;
+                (n, n)
+            }
+            Some((a, b)) if a.trim().is_empty() => (1, num(b)?),
+            Some((a, b)) if b.trim().is_empty() => (num(a)?, usize::MAX),
+            Some((a, b)) => (num(a)?, num(b)?),
+        };
+        if first > last {
+            return Err(bad());
+        }
+
+        Ok(LineRange { first, last })
+    }
+}
+
+/// A range of chunks delimited by patterns.
+#[derive(Debug)]
+pub struct Between {
+    pub start: Regex,
+    pub end: Regex,
+}
+
+/// Which chunks to edit.
+#[derive(Debug, Default)]
+pub struct Address {
+    pub lines: Vec<LineRange>,
+    pub wheres: Vec<Regex>,
+    pub where_nots: Vec<Regex>,
+    pub betweens: Vec<Between>,
+}
+
+impl Address {
+    /// Start checking a new input against the address.
+    pub fn selector(&self) -> Selector<'_> {
+        Selector {
+            address: self,
+            line: 0,
+            inside: vec![false; self.betweens.len()],
+        }
+    }
+}
+
+/// Keeps track of where in the input we are.
+pub struct Selector<'a> {
+    address: &'a Address,
+    line: usize,
+    /// Whether we're currently in each of the `betweens` ranges.
+    inside: Vec<bool>,
+}
+
+impl<'a> Selector<'a> {
+    /// Whether the next chunk of the input should be edited.
+    pub fn selects(&mut self, chunk: &[u8]) -> bool {
+        let addr = self.address;
+        self.line += 1;
+
+        // This has to be checked for every chunk to keep track of the
+        // ranges, even if something else has already ruled this one out.
+        let mut between = addr.betweens.is_empty();
+        for (b, inside) in addr.betweens.iter().zip(self.inside.iter_mut()) {
+            if *inside {
+                between = true;
+                if b.end.is_match(chunk) {
+                    *inside = false;
+                }
+            } else if b.start.is_match(chunk) {
+                between = true;
+                *inside = true;
+            }
+        }
+
+        between
+            && (addr.lines.is_empty() || addr.lines.iter().any(|r| r.contains(self.line)))
+            && addr.wheres.iter().all(|re| re.is_match(chunk))
+            && !addr.where_nots.iter().any(|re| re.is_match(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> (usize, usize) {
+        let r = LineRange::try_from(s).unwrap();
+        (r.first, r.last)
+    }
+
+    #[test]
+    fn line_range_parse() {
+        assert_eq!(range("10-20"), (10, 20));
+        assert_eq!(range("10-"), (10, usize::MAX));
+        assert_eq!(range("-20"), (1, 20));
+        assert_eq!(range("10"), (10, 10));
+        assert_eq!(range(" 3 - 4 "), (3, 4));
+        for bad in ["", "-", "0", "0-3", "5-2", "a-b", "1-2-3", "1.5"] {
+            assert!(LineRange::try_from(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn line_range_contains() {
+        let r = LineRange::try_from("2-3").unwrap();
+        assert!(!r.contains(1));
+        assert!(r.contains(2));
+        assert!(r.contains(3));
+        assert!(!r.contains(4));
+        assert!(LineRange::try_from("2-").unwrap().contains(usize::MAX));
+    }
+
+    #[test]
+    fn selects_lines() {
+        let address = Address {
+            lines: vec![
+                LineRange::try_from("2").unwrap(),
+                LineRange::try_from("4-5").unwrap(),
+            ],
+            ..Address::default()
+        };
+        let mut selector = address.selector();
+        let picked: Vec<bool> = (0..6).map(|_| selector.selects(b"x")).collect();
+        assert_eq!(picked, [false, true, false, true, true, false]);
+    }
+}
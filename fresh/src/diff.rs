@@ -0,0 +1,375 @@
+/*!
+Unified diffs between the original and altered versions of the text.
+
+Lines are matched up with Myers' algorithm (the linear-space version, so
+large inputs with lots of changes don't need quadratic memory).
+*/
+use std::io::{self, Write};
+
+/// Split `text` into lines, each including its line ending (if any).
+fn lines(text: &[u8]) -> Vec<&[u8]> {
+    text.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Finds the pairs of lines (by index) that the two versions have in
+/// common.
+struct Matcher<'a> {
+    a: &'a [&'a [u8]],
+    b: &'a [&'a [u8]],
+    /// Furthest-reaching paths forward and backward, by diagonal.
+    vf: Vec<usize>,
+    vb: Vec<usize>,
+    pairs: Vec<(usize, usize)>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(a: &'a [&'a [u8]], b: &'a [&'a [u8]]) -> Matcher<'a> {
+        let size = 2 * (a.len() + b.len()) + 3;
+        Matcher {
+            a,
+            b,
+            vf: vec![0; size],
+            vb: vec![0; size],
+            pairs: Vec::new(),
+        }
+    }
+
+    /**
+    Find the "middle snake" of the shortest edit script turning
+    `a[a0..a1]` into `b[b0..b1]`: a run of matching lines (possibly
+    empty) that an optimal script passes through about halfway. Returns
+    its start and end, relative to `(a0, b0)`.
+    */
+    fn middle_snake(&mut self, a0: usize, a1: usize, b0: usize, b1: usize) -> [usize; 4] {
+        let (n, m) = ((a1 - a0) as isize, (b1 - b0) as isize);
+        let delta = n - m;
+        let odd = delta & 1 != 0;
+        let off = n + m + 1;
+        let idx = |k: isize| (k + off) as usize;
+        let (a, b) = (self.a, self.b);
+
+        self.vf[idx(1)] = 0;
+        self.vb[idx(1)] = 0;
+        for d in 0..=((n + m + 1) / 2) {
+            for k in (-d..=d).step_by(2) {
+                let mut x = if k == -d || (k != d && self.vf[idx(k - 1)] < self.vf[idx(k + 1)]) {
+                    self.vf[idx(k + 1)] as isize
+                } else {
+                    self.vf[idx(k - 1)] as isize + 1
+                };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+                while x < n && y < m && a[a0 + x as usize] == b[b0 + y as usize] {
+                    x += 1;
+                    y += 1;
+                }
+                self.vf[idx(k)] = x as usize;
+
+                let kr = delta - k;
+                if odd && -d < kr && kr < d && x + self.vb[idx(kr)] as isize >= n {
+                    return [x0 as usize, y0 as usize, x as usize, y as usize];
+                }
+            }
+
+            for k in (-d..=d).step_by(2) {
+                let mut x = if k == -d || (k != d && self.vb[idx(k - 1)] < self.vb[idx(k + 1)]) {
+                    self.vb[idx(k + 1)] as isize
+                } else {
+                    self.vb[idx(k - 1)] as isize + 1
+                };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+                while x < n && y < m && a[a1 - 1 - x as usize] == b[b1 - 1 - y as usize] {
+                    x += 1;
+                    y += 1;
+                }
+                self.vb[idx(k)] = x as usize;
+
+                let kf = delta - k;
+                if !odd && -d <= kf && kf <= d && self.vf[idx(kf)] as isize + x >= n {
+                    return [
+                        (n - x) as usize,
+                        (m - y) as usize,
+                        (n - x0) as usize,
+                        (m - y0) as usize,
+                    ];
+                }
+            }
+        }
+
+        // An edit script can't be longer than n + m, so this shouldn't
+        // happen; treating everything as changed is still correct.
+        [0, 0, 0, 0]
+    }
+
+    /// Find the matching lines of `a[a0..a1]` and `b[b0..b1]`.
+    fn find(&mut self, mut a0: usize, mut a1: usize, mut b0: usize, mut b1: usize) {
+        while a0 < a1 && b0 < b1 && self.a[a0] == self.b[b0] {
+            self.pairs.push((a0, b0));
+            a0 += 1;
+            b0 += 1;
+        }
+        let mut suffix = 0;
+        while a0 < a1 && b0 < b1 && self.a[a1 - 1] == self.b[b1 - 1] {
+            a1 -= 1;
+            b1 -= 1;
+            suffix += 1;
+        }
+
+        if a0 < a1 && b0 < b1 {
+            let [x0, y0, x1, y1] = self.middle_snake(a0, a1, b0, b1);
+            // Both halves have to be smaller than the whole, or this would
+            // never end. (If not, everything here is just a change.)
+            if (x1, y1) != (0, 0) && (x0, y0) != (a1 - a0, b1 - b0) {
+                self.find(a0, a0 + x0, b0, b0 + y0);
+                for i in 0..(x1 - x0) {
+                    self.pairs.push((a0 + x0 + i, b0 + y0 + i));
+                }
+                self.find(a0 + x1, a1, b0 + y1, b1);
+            }
+        }
+
+        for i in 0..suffix {
+            self.pairs.push((a1 + i, b1 + i));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Line {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+/// The lines of both versions, in order, and what happened to each.
+fn script(a: &[&[u8]], b: &[&[u8]]) -> Vec<Line> {
+    let mut matcher = Matcher::new(a, b);
+    matcher.find(0, a.len(), 0, b.len());
+
+    let mut lines = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    for &(ai, bj) in matcher
+        .pairs
+        .iter()
+        .chain(std::iter::once(&(a.len(), b.len())))
+    {
+        lines.extend((i..ai).map(Line::Removed));
+        lines.extend((j..bj).map(Line::Added));
+        if ai < a.len() {
+            lines.push(Line::Same(ai, bj));
+        }
+        i = ai + 1;
+        j = bj + 1;
+    }
+
+    lines
+}
+
+fn write_line<W: Write + ?Sized>(out: &mut W, prefix: u8, line: &[u8]) -> io::Result<()> {
+    out.write_all(&[prefix])?;
+    out.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n\\ No newline at end of file\n")?;
+    }
+    Ok(())
+}
+
+/// Format one side of a hunk header, like `-12,3`.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        // An empty range is said to start at the line before it.
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        n => format!("{},{}", start + 1, n),
+    }
+}
+
+/**
+Write a unified diff turning `old` into `new` (with `context` lines of
+context around each change) to `out`, under a header naming the file
+`name`. Nothing is written if they're the same.
+*/
+pub fn write<W: Write + ?Sized>(
+    out: &mut W,
+    name: &str,
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+) -> io::Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let (a, b) = (lines(old), lines(new));
+    let script = script(&a, &b);
+
+    writeln!(out, "--- {}", name)?;
+    writeln!(out, "+++ {}", name)?;
+
+    let changes: Vec<usize> = script
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| !matches!(l, Line::Same(..)))
+        .map(|(n, _)| n)
+        .collect();
+
+    // Lines of each version before the current hunk.
+    let (mut a_start, mut b_start) = (0, 0);
+    let mut counted = 0;
+    let mut n = 0;
+    while n < changes.len() {
+        // Extend the hunk to include any changes whose context overlaps.
+        let first = changes[n];
+        let mut last = first;
+        while n + 1 < changes.len() && changes[n + 1] - last <= 2 * context + 1 {
+            n += 1;
+            last = changes[n];
+        }
+        n += 1;
+
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(script.len());
+        let hunk = &script[start..end];
+
+        for line in script[counted..start].iter() {
+            match line {
+                Line::Same(..) => {
+                    a_start += 1;
+                    b_start += 1;
+                }
+                Line::Removed(_) => a_start += 1,
+                Line::Added(_) => b_start += 1,
+            }
+        }
+        counted = start;
+        let a_len = hunk.iter().filter(|l| !matches!(l, Line::Added(_))).count();
+        let b_len = hunk
+            .iter()
+            .filter(|l| !matches!(l, Line::Removed(_)))
+            .count();
+        writeln!(
+            out,
+            "@@ -{} +{} @@",
+            range(a_start, a_len),
+            range(b_start, b_len)
+        )?;
+
+        for line in hunk.iter() {
+            match *line {
+                Line::Same(i, _) => write_line(out, b' ', a[i])?,
+                Line::Removed(i) => write_line(out, b'-', a[i])?,
+                Line::Added(j) => write_line(out, b'+', b[j])?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str, context: usize) -> String {
+        let mut out = Vec::new();
+        write(&mut out, "f", old.as_bytes(), new.as_bytes(), context).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// The length of the longest common subsequence, the slow way.
+    fn lcs(a: &[&[u8]], b: &[&[u8]]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = match a[i] == b[j] {
+                    true => table[i + 1][j + 1] + 1,
+                    false => table[i + 1][j].max(table[i][j + 1]),
+                };
+            }
+        }
+        table[0][0]
+    }
+
+    #[test]
+    fn same() {
+        assert_eq!(diff("a\nb\n", "a\nb\n", 3), "");
+    }
+
+    #[test]
+    fn one_change() {
+        assert_eq!(
+            diff("a\nb\nc\nd\ne\n", "a\nb\nX\nd\ne\n", 1),
+            "--- f\n+++ f\n@@ -2,3 +2,3 @@\n b\n-c\n+X\n d\n"
+        );
+    }
+
+    #[test]
+    fn separate_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\ntwo\n3\n4\n5\n6\n7\n8\nnine\n10\n";
+        assert_eq!(
+            diff(old, new, 1),
+            "--- f\n+++ f\n\
+             @@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n\
+             @@ -8,3 +8,3 @@\n 8\n-9\n+nine\n 10\n"
+        );
+        // With more context, they overlap and become one.
+        let merged = diff(old, new, 3);
+        assert!(merged.starts_with("--- f\n+++ f\n@@ -1,10 +1,10 @@\n"));
+        assert_eq!(merged.matches("@@").count(), 2);
+    }
+
+    #[test]
+    fn empty_sides() {
+        assert_eq!(diff("", "x\n", 3), "--- f\n+++ f\n@@ -0,0 +1 @@\n+x\n");
+        assert_eq!(
+            diff("x\ny\n", "", 3),
+            "--- f\n+++ f\n@@ -1,2 +0,0 @@\n-x\n-y\n"
+        );
+    }
+
+    #[test]
+    fn no_final_newline() {
+        assert_eq!(
+            diff("a\nb", "a\nc", 3),
+            "--- f\n+++ f\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn scripts_are_shortest() {
+        // A small linear congruential generator, so the cases are the
+        // same every time.
+        let mut seed: u32 = 12345;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as usize
+        };
+        let words: [&[u8]; 4] = [b"a\n", b"b\n", b"c\n", b"d\n"];
+
+        for _ in 0..500 {
+            let a: Vec<&[u8]> = (0..next() % 20).map(|_| words[next() % 4]).collect();
+            let b: Vec<&[u8]> = (0..next() % 20).map(|_| words[next() % 4]).collect();
+            let script = script(&a, &b);
+
+            let mut old: Vec<&[u8]> = Vec::new();
+            let mut new: Vec<&[u8]> = Vec::new();
+            let mut same = 0;
+            for line in script.iter() {
+                match *line {
+                    Line::Same(i, j) => {
+                        assert_eq!(a[i], b[j]);
+                        old.push(a[i]);
+                        new.push(b[j]);
+                        same += 1;
+                    }
+                    Line::Removed(i) => old.push(a[i]),
+                    Line::Added(j) => new.push(b[j]),
+                }
+            }
+            assert_eq!(old, a);
+            assert_eq!(new, b);
+            assert_eq!(same, lcs(&a, &b), "{:?} {:?}", a, b);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+/*!
+Editing files in place.
+
+The altered text is written to a temporary file in the same directory
+as the original, which is then renamed over it, so the original is never
+left half-written. Files that wouldn't change aren't touched at all.
+*/
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use regex::bytes::Regex;
+
+use crate::{alter_chunk, err::FrErr, opt::Opts, transform};
+
+/// A name for the temporary file to write the new version of `path` to.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".fresh-{}", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Give the file `f` the same owner and group as `meta`, if we're
+/// allowed to. (If we're not, it's owned by whoever is running `fresh`,
+/// the same as any other new file.)
+#[cfg(unix)]
+fn copy_owner(f: &File, meta: &Metadata) {
+    use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+    // SAFETY: `f` is open, so its descriptor is valid for the whole call.
+    unsafe {
+        libc::fchown(f.as_raw_fd(), meta.uid(), meta.gid());
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_f: &File, _meta: &Metadata) {}
+
+/// Write `text` to the new file at `path`, giving it the permissions
+/// (and owner, if possible) in `meta`.
+fn write_new(path: &Path, text: &[u8], meta: &Metadata) -> std::io::Result<()> {
+    let mut f: File = OpenOptions::new().write(true).create_new(true).open(path)?;
+    f.write_all(text)?;
+    f.sync_all()?;
+    copy_owner(&f, meta);
+    fs::set_permissions(path, meta.permissions())
+}
+
+/// Keep the original version of `path` as `backup`. A hard link is all
+/// that's necessary, because the original is about to be replaced (not
+/// overwritten); if that doesn't work, copy it.
+fn keep_backup(path: &Path, backup: &Path) -> std::io::Result<()> {
+    if backup.symlink_metadata().is_ok() {
+        fs::remove_file(backup)?;
+    }
+    if fs::hard_link(path, backup).is_err() {
+        fs::copy(path, backup)?;
+    }
+    Ok(())
+}
+
+/**
+Alter a whole file's worth of `original` text. Unlike when streaming,
+each chunk keeps the delimiter it had (unless a `--newline` was given),
+and the last one doesn't get one if it didn't have one, so chunks that
+aren't changed come out exactly as they went in.
+*/
+pub fn alter(opts: &Opts, original: &[u8]) -> Result<Vec<u8>, FrErr> {
+    let mut altered: Vec<u8> = Vec::with_capacity(original.len());
+    if opts.whole {
+        transform(opts, original, &mut altered)?;
+        return Ok(altered);
+    }
+
+    let delimiter = Regex::new(&opts.delimiter)?;
+    let mut selector = opts.address.selector();
+    let mut write = |chunk: &[u8], delim: &[u8]| {
+        if let Some(text) = alter_chunk(opts, &mut selector, chunk) {
+            altered.extend_from_slice(&text);
+            match (opts.keep_delimiters, &opts.newline) {
+                (true, _) => altered.extend_from_slice(delim),
+                (false, Some(nl_bytes)) if !delim.is_empty() => altered.extend_from_slice(nl_bytes),
+                (false, _) => {}
+            }
+        }
+    };
+
+    let mut start = 0;
+    for m in delimiter.find_iter(original) {
+        write(&original[start..m.start()], m.as_bytes());
+        start = m.end();
+    }
+    if start < original.len() {
+        write(&original[start..], &[]);
+    }
+
+    Ok(altered)
+}
+
+/**
+Alter the file at `path`, keeping the original version in a file with
+`backup` appended to its name (if given). If `path` is a symbolic link,
+the file it points to is the one altered.
+*/
+pub fn in_place(opts: &Opts, path: &Path, backup: Option<&str>) -> Result<(), FrErr> {
+    let path = fs::canonicalize(path).map_err(FrErr::file(path))?;
+    let meta = fs::metadata(&path).map_err(FrErr::file(&path))?;
+    let original = fs::read(&path).map_err(FrErr::file(&path))?;
+
+    let altered = alter(opts, &original)?;
+    if altered == original {
+        return Ok(());
+    }
+
+    let tmp = temp_path(&path);
+    if let Err(e) = write_new(&tmp, &altered, &meta) {
+        let _ = fs::remove_file(&tmp);
+        return Err(FrErr::File(tmp, e));
+    }
+
+    if let Some(suffix) = backup {
+        let mut name = path.clone().into_os_string();
+        name.push(suffix);
+        let backup = PathBuf::from(name);
+        if let Err(e) = keep_backup(&path, &backup) {
+            let _ = fs::remove_file(&tmp);
+            return Err(FrErr::File(backup, e));
+        }
+    }
+
+    if let Err(e) = fs::rename(&tmp, &path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(FrErr::File(path, e));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address::Address,
+        opt::{MatchMode, OutputMode},
+        rule::{Defaults, Flags, Rule},
+    };
+
+    fn opts(pattern: &str, replace: &str) -> Opts {
+        let defaults = Defaults {
+            match_mode: MatchMode::Regex,
+            max: usize::MAX,
+            flags: Flags::default(),
+            transform: false,
+        };
+        Opts {
+            rules: vec![Rule::new(pattern, replace, defaults).unwrap()],
+            address: Address::default(),
+            output_mode: OutputMode::Replace,
+            delimiter: String::from(r"\r?\n"),
+            newline: Some(Vec::from("\n")),
+            keep_delimiters: true,
+            whole: false,
+            diff: None,
+            check: false,
+        }
+    }
+
+    #[test]
+    fn unchanged_crlf() {
+        let original = b"one\r\ntwo\r\nthree\r\n";
+        assert_eq!(alter(&opts("zzz", "y"), original).unwrap(), original);
+    }
+
+    #[test]
+    fn changed_crlf() {
+        let altered = alter(&opts("two", "2"), b"one\r\ntwo\r\nthree").unwrap();
+        assert_eq!(altered, b"one\r\n2\r\nthree");
+    }
+
+    #[test]
+    fn mixed_endings() {
+        let original = b"a\nb\r\n\nc";
+        assert_eq!(alter(&opts("b", "B"), original).unwrap(), b"a\nB\r\n\nc");
+    }
+
+    #[test]
+    fn given_newline() {
+        let mut opts = opts("a", "A");
+        opts.keep_delimiters = false;
+        opts.newline = Some(Vec::from(";"));
+        assert_eq!(alter(&opts, b"a\r\nb\nc").unwrap(), b"A;b;c");
+    }
+}
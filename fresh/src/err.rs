@@ -6,6 +6,7 @@ use std::{
     fmt::{Display, Formatter},
     io,
     ops::Deref,
+    path::PathBuf,
 };
 
 use regex_chunker::RcErr;
@@ -13,7 +14,16 @@ use regex_chunker::RcErr;
 #[derive(Debug)]
 pub enum FrErr {
     Io(io::Error),
+    /// An I/O error involving a particular file.
+    File(PathBuf, io::Error),
     Regex(regex::Error),
+    /// A bad substitution rule (and where it came from).
+    Rule {
+        origin: String,
+        msg: String,
+    },
+    /// A bad replacement.
+    Template(String),
     Misc(Box<dyn Error>),
 }
 
@@ -23,6 +33,13 @@ impl From<io::Error> for FrErr {
     }
 }
 
+impl FrErr {
+    /// Attach the path of the file involved to an I/O error.
+    pub fn file<P: Into<PathBuf>>(path: P) -> impl FnOnce(io::Error) -> FrErr {
+        move |e| FrErr::File(path.into(), e)
+    }
+}
+
 impl From<regex::Error> for FrErr {
     fn from(e: regex::Error) -> Self {
         FrErr::Regex(e)
@@ -44,6 +61,12 @@ impl Display for FrErr {
         match self {
             FrErr::Regex(ref e) => write!(f, "regex error: {}", e),
             FrErr::Io(ref e) => write!(f, "I/O error: {}", &e),
+            FrErr::File(ref p, ref e) => write!(f, "I/O error with \"{}\": {}", p.display(), &e),
+            FrErr::Rule {
+                ref origin,
+                ref msg,
+            } => write!(f, "error in rule ({}): {}", origin, msg),
+            FrErr::Template(ref msg) => write!(f, "error in replacement: {}", msg),
             FrErr::Misc(ref e) => write!(f, "{}", &e),
         }
     }
@@ -53,7 +76,10 @@ impl Error for FrErr {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             FrErr::Io(ref e) => Some(e),
+            FrErr::File(_, ref e) => Some(e),
             FrErr::Regex(ref e) => Some(e),
+            FrErr::Rule { .. } => None,
+            FrErr::Template(_) => None,
             FrErr::Misc(ref e) => Some(e.deref()),
         }
     }
@@ -1,171 +1,167 @@
+mod address;
+mod diff;
+mod edit;
 mod err;
 mod opt;
+mod rule;
+mod template;
+mod whole;
 
-use std::{borrow::Cow, io::Write};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+};
 
-use regex::bytes::Regex;
 use regex_chunker::ByteChunker;
 
+use address::Selector;
 use err::FrErr;
-use opt::{MatchMode, Opts, OutputMode};
+use opt::{Opts, OutputMode, Target};
 
-fn find_subslice<T>(haystack: &[T], needle: &[T]) -> Option<usize>
-where
-    T: PartialEq,
-{
-    if needle.len() > haystack.len() {
+/**
+Pass one `chunk` of the input through the rules in order, if the
+`selector` picks it. Each rule either replaces or extracts (and possibly
+replaces) text according to the semantics of the
+[`Regex::replace*`](https://docs.rs/regex/latest/regex/struct.Regex.html#method.replace)
+family of functions, and the next rule works on the result. Returns
+`None` if nothing should be written for this chunk.
+*/
+pub fn alter_chunk<'c>(
+    opts: &Opts,
+    selector: &mut Selector,
+    chunk: &'c [u8],
+) -> Option<Cow<'c, [u8]>> {
+    let mut text: Cow<[u8]> = Cow::Borrowed(chunk);
+
+    // Chunks that aren't addressed are passed through as they are
+    // (unless we're extracting).
+    if selector.selects(chunk) {
+        for rule in opts.rules.iter() {
+            match opts.output_mode {
+                OutputMode::Replace => {
+                    let altered = rule.replace(&text);
+                    if let Cow::Owned(v) = altered {
+                        text = Cow::Owned(v);
+                    }
+                }
+                OutputMode::Extract => {
+                    let mut buff: Vec<u8> = Vec::new();
+                    rule.extract(&text, &mut buff);
+                    text = Cow::Owned(buff);
+                }
+            }
+        }
+    } else if let OutputMode::Extract = opts.output_mode {
         return None;
     }
 
-    for (n, w) in haystack.windows(needle.len()).enumerate() {
-        if w == needle {
-            return Some(n);
+    if let OutputMode::Extract = opts.output_mode {
+        if text.is_empty() {
+            return None;
         }
     }
-
-    None
+    Some(text)
 }
 
 /**
-Read input stream line-by-line, either replacing or extracting (and
-possibly replacing) text according to the semantics of the
-[`Regex::replace*`](https://docs.rs/regex/latest/regex/struct.Regex.html#method.replace)
-family of functions.
+Read the input chunk by chunk (by default, line by line), altering each
+one with [`alter_chunk`].
 */
-fn regex_match(mut opts: Opts) -> Result<(), FrErr> {
-    let re = Regex::new(&opts.pattern)?;
-    let chunker = ByteChunker::new(opts.input, &opts.delimiter)?;
-
-    match opts.output_mode {
-        OutputMode::Replace(repl) => {
-            let repl = repl.as_bytes();
-            for chunk in chunker {
-                let chunk = chunk?;
-                let altered = re.replacen(&chunk, opts.max, repl);
-
-                match altered {
-                    Cow::Owned(mut v) => {
-                        if let Some(ref nl_bytes) = opts.newline {
-                            v.extend_from_slice(nl_bytes);
-                        }
-                        opts.output.write_all(&v)?;
-                    }
-                    Cow::Borrowed(b) => {
-                        opts.output.write_all(b)?;
-                        if let Some(ref nl_bytes) = opts.newline {
-                            opts.output.write_all(nl_bytes)?;
-                        }
-                    }
-                }
-            }
-        }
-        OutputMode::Extract(repl) => {
-            let repl = repl.as_bytes();
-            let mut buff: Vec<u8> = Vec::new();
-            for chunk in chunker {
-                let chunk = chunk?;
-
-                for cap in re.captures_iter(&chunk) {
-                    cap.expand(repl, &mut buff);
-                }
-
-                if !buff.is_empty() {
-                    if let Some(ref nl_bytes) = opts.newline {
-                        buff.extend_from_slice(nl_bytes);
-                    }
-                    opts.output.write_all(&buff)?;
-                    buff.clear();
-                }
+pub fn transform<R: Read>(opts: &Opts, input: R, output: &mut dyn Write) -> Result<(), FrErr> {
+    if opts.whole {
+        return whole::transform(opts, input, output);
+    }
+    let chunker = ByteChunker::new(input, &opts.delimiter)?;
+    let mut selector = opts.address.selector();
+
+    for chunk in chunker {
+        let chunk = chunk?;
+        if let Some(text) = alter_chunk(opts, &mut selector, &chunk) {
+            output.write_all(&text)?;
+            if let Some(ref nl_bytes) = opts.newline {
+                output.write_all(nl_bytes)?;
             }
         }
     }
 
-    opts.output.flush()?;
     Ok(())
 }
 
 /**
-Read the input stream line-by line, either (as governed by
-`opts.output_mode`) replacing or extracting (and maybe also
-replacing) text.
+Instead of altering anything, show how each input would change (as a diff
+if `--diff` was given, otherwise just by naming it). Returns whether any
+of them would.
 */
-fn static_match(mut opts: Opts) -> Result<(), FrErr> {
-    let patt = opts.pattern.as_bytes();
-    let chunker = ByteChunker::new(opts.input, &opts.delimiter)?;
-    let mut buff: Vec<u8> = Vec::new();
-
-    match opts.output_mode {
-        OutputMode::Replace(repl) => {
-            let repl = repl.as_bytes();
-            for chunk in chunker {
-                let chunk = chunk?;
-                let mut subslice = &chunk[..];
-                let mut n_replaced: usize = 0;
-
-                while n_replaced < opts.max {
-                    if let Some(n) = find_subslice(subslice, patt) {
-                        buff.extend_from_slice(&subslice[..n]);
-                        buff.extend_from_slice(repl);
-                        n_replaced += 1;
-                        let offs = n + patt.len();
-                        subslice = &subslice[offs..];
-                    } else {
-                        break;
-                    }
-                }
-
-                if !subslice.is_empty() {
-                    buff.extend_from_slice(subslice)
-                }
-                if let Some(ref nl_bytes) = opts.newline {
-                    buff.extend_from_slice(nl_bytes);
-                }
-                opts.output.write_all(&buff)?;
-                buff.clear();
+fn preview(opts: &Opts, files: &[PathBuf], output: &mut dyn Write) -> Result<bool, FrErr> {
+    let mut changed = false;
+    let mut show = |name: &str, original: &[u8]| -> Result<(), FrErr> {
+        let altered = edit::alter(opts, original)?;
+        if altered != original {
+            changed = true;
+            match opts.diff {
+                Some(context) => diff::write(output, name, original, &altered, context)?,
+                None => writeln!(output, "{}", name)?,
             }
         }
-        OutputMode::Extract(repl) => {
-            let repl = repl.as_bytes();
-            for chunk in chunker {
-                let chunk = chunk?;
-                let mut subslice = &chunk[..];
-                let mut n_replaced: usize = 0;
-
-                while n_replaced < opts.max {
-                    if let Some(n) = find_subslice(subslice, patt) {
-                        buff.extend_from_slice(repl);
-                        n_replaced += 1;
-                        let offs = n + repl.len();
-                        subslice = &subslice[offs..];
-                    } else {
-                        break;
-                    }
-                }
+        Ok(())
+    };
 
-                if !buff.is_empty() {
-                    if let Some(ref nl_bytes) = opts.newline {
-                        buff.extend_from_slice(nl_bytes);
-                    }
-                    opts.output.write_all(&buff)?;
-                    buff.clear();
-                }
-            }
-        }
+    if files.is_empty() {
+        let mut text = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut text)?;
+        show("<stdin>", &text)?;
     }
+    for path in files.iter() {
+        let text = std::fs::read(path).map_err(FrErr::file(path))?;
+        show(&path.display().to_string(), &text)?;
+    }
+    output.flush()?;
 
-    opts.output.flush()?;
-    Ok(())
+    Ok(changed)
 }
 
-fn main() -> Result<(), FrErr> {
-    let opts = Opts::new()?;
+/// Returns whether `--check` found anything that would change.
+fn wrapped_main() -> Result<bool, FrErr> {
+    let (opts, target) = Opts::new()?;
 
-    let mode = opts.match_mode;
+    if opts.diff.is_some() || opts.check {
+        let changed = match target {
+            Target::Stream { files, mut output } => preview(&opts, &files, &mut output)?,
+            Target::InPlace { files, .. } => preview(&opts, &files, &mut std::io::stdout().lock())?,
+        };
+        return Ok(opts.check && changed);
+    }
 
-    match mode {
-        MatchMode::Regex => regex_match(opts)?,
-        MatchMode::Verbatim => static_match(opts)?,
+    match target {
+        Target::Stream { files, mut output } => {
+            if files.is_empty() {
+                transform(&opts, std::io::stdin().lock(), &mut output)?;
+            }
+            for path in files.iter() {
+                let f = File::open(path).map_err(FrErr::file(path))?;
+                transform(&opts, f, &mut output)?;
+            }
+            output.flush()?;
+        }
+        Target::InPlace { files, backup } => {
+            for path in files.iter() {
+                edit::in_place(&opts, path, backup.as_deref())?;
+            }
+        }
     }
 
-    Ok(())
+    Ok(false)
+}
+
+fn main() {
+    match wrapped_main() {
+        Ok(false) => {}
+        Ok(true) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", &e);
+            std::process::exit(2);
+        }
+    }
 }
@@ -1,135 +1,335 @@
-/*!
-Parsing command-line options.
-*/
-use std::{
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
-};
-
-use clap::Parser;
-
-use crate::FrErr;
-
-#[cfg(not(windows))]
-static NEWLINE: &str = "\n";
-#[cfg(windows)]
-static NEWLINE: &str = "\r\n";
-
-static DEFAULT_REGEX_EXTRACT: &str = "$0";
-
-#[derive(Clone, Debug)]
-pub enum OutputMode {
-    Replace(String),
-    Extract(String),
-}
-
-#[derive(Clone, Copy, Debug)]
-pub enum MatchMode {
-    Regex,
-    Verbatim,
-}
-
-#[derive(Debug, Parser)]
-#[command(author, version, about)]
-struct CliOpts {
-    /// Pattern to find.
-    pattern: String,
-
-    /// Optional replacement.
-    replace: Option<String>,
-
-    /// Maximum number of replacements per line (default is all).
-    #[arg(short, long, value_name = "N")]
-    max: Option<usize>,
-
-    /// Print only found pattern (default is print everything).
-    #[arg(short = 'x', long = "extract")]
-    extract: bool,
-
-    /// Do simple verbatim string matching (default is regex matching).
-    #[arg(short, long)]
-    simple: bool,
-
-    /// Delimiter to separate "lines".
-    #[arg(short, long, value_name = "PATT",
-        default_value_t = String::from(r#"\r?\n"#))]
-    delimiter: String,
-
-    /// Print something other than a newline between chunks.
-    #[arg(short, long, value_name = "NL")]
-    newline: Option<Option<String>>,
-
-    /// Input file (default is stdin).
-    #[arg(short, long)]
-    input: Option<PathBuf>,
-
-    /// Output file (default is stdout).
-    #[arg(short, long)]
-    output: Option<PathBuf>,
-}
-
-pub struct Opts {
-    pub pattern: String,
-    pub max: usize,
-    pub output_mode: OutputMode,
-    pub match_mode: MatchMode,
-    pub delimiter: String,
-    pub newline: Option<Vec<u8>>,
-    pub input: Box<dyn Read>,
-    pub output: Box<dyn Write>,
-}
-
-impl Opts {
-    pub fn new() -> Result<Self, FrErr> {
-        let clio = CliOpts::parse();
-
-        let max = clio.max.unwrap_or(usize::MAX);
-
-        let output_mode = match (clio.extract, clio.replace) {
-            (_, None) => {
-                if clio.simple {
-                    OutputMode::Extract(clio.pattern.clone())
-                } else {
-                    OutputMode::Extract(DEFAULT_REGEX_EXTRACT.into())
-                }
-            }
-            (true, Some(repl)) => OutputMode::Extract(repl),
-            (false, Some(repl)) => OutputMode::Replace(repl),
-        };
-
-        let match_mode = if clio.simple {
-            MatchMode::Verbatim
-        } else {
-            MatchMode::Regex
-        };
-
-        let input: Box<dyn Read> = match clio.input {
-            Some(pbuf) => Box::new(File::open(pbuf)?),
-            None => Box::new(std::io::stdin().lock()),
-        };
-        let output: Box<dyn Write> = match clio.output {
-            Some(pbuf) => Box::new(File::create(pbuf)?),
-            None => Box::new(std::io::stdout().lock()),
-        };
-        let newline = match clio.newline {
-            // If the argument is absent, just use a newline sequence.
-            None => Some(Vec::from(NEWLINE)),
-            // If the argument is present but has no value, make it none.
-            Some(None) => None,
-            // If the argument is present and has a value, use that.
-            Some(Some(s)) => Some(Vec::from(s)),
-        };
-
-        Ok(Opts {
-            pattern: clio.pattern,
-            delimiter: clio.delimiter,
-            newline,
-            max,
-            output_mode,
-            match_mode,
-            input,
-            output,
-        })
-    }
-}
+/*!
+Parsing command-line options.
+*/
+use std::{convert::TryFrom, fs::File, io::Write, path::PathBuf};
+
+use clap::{CommandFactory, FromArgMatches, Parser};
+
+use crate::{
+    address::{Address, Between, LineRange},
+    rule::{Defaults, Flags, Rule},
+    FrErr,
+};
+
+#[cfg(not(windows))]
+static NEWLINE: &str = "\n";
+#[cfg(windows)]
+static NEWLINE: &str = "\r\n";
+
+#[derive(Clone, Copy, Debug)]
+pub enum OutputMode {
+    Replace,
+    Extract,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MatchMode {
+    Regex,
+    Verbatim,
+}
+
+/// Where the text comes from and where it goes.
+pub enum Target {
+    /// Read the `files` one after another (or stdin if there aren't any),
+    /// and write everything to `output`.
+    Stream {
+        files: Vec<PathBuf>,
+        output: Box<dyn Write>,
+    },
+    /// Edit each of the `files`, keeping a copy of the original with
+    /// `backup` added to its name (if given).
+    InPlace {
+        files: Vec<PathBuf>,
+        backup: Option<String>,
+    },
+}
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct CliOpts {
+    /// Pattern to find.
+    #[arg(required_unless_present_any = ["expression", "script"])]
+    pattern: Option<String>,
+
+    /// Optional replacement.
+    replace: Option<String>,
+
+    /// Files to read (default is stdin).
+    #[arg(value_name = "FILE", conflicts_with = "input")]
+    files: Vec<PathBuf>,
+
+    /// Apply this RULE (written PATTERN=>REPLACEMENT); all the arguments
+    /// are FILEs.
+    #[arg(short, long, value_name = "RULE", allow_hyphen_values = true)]
+    expression: Vec<String>,
+
+    /// Apply the rules in this SCRIPT file, one per line; all the
+    /// arguments are FILEs.
+    #[arg(short = 'f', long = "file", value_name = "SCRIPT")]
+    script: Vec<PathBuf>,
+
+    /// Maximum number of replacements per line (default is all).
+    #[arg(short, long, value_name = "N")]
+    max: Option<usize>,
+
+    /// Only edit lines in this RANGE (like 10-20, 10-, -20, or 10).
+    #[arg(long, value_name = "RANGE", allow_hyphen_values = true)]
+    lines: Vec<String>,
+
+    /// Only edit lines that match PATT.
+    #[arg(long = "where", value_name = "PATT")]
+    where_match: Vec<String>,
+
+    /// Only edit lines that don't match PATT.
+    #[arg(long, value_name = "PATT")]
+    where_not: Vec<String>,
+
+    /// Only edit lines from one that matches START through the next one
+    /// that matches END.
+    #[arg(long, num_args = 2, value_names = ["START", "END"])]
+    between: Vec<String>,
+
+    /// Print only found pattern (default is print everything).
+    #[arg(short = 'x', long = "extract")]
+    extract: bool,
+
+    /// Do simple verbatim string matching (default is regex matching).
+    #[arg(short, long)]
+    simple: bool,
+
+    /// Allow case conversion (\U, \L, \E, \u, \l) and filters (like
+    /// ${1:upper}) in the replacement.
+    #[arg(short, long)]
+    transform: bool,
+
+    /// Ignore case when matching.
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Only match whole words.
+    #[arg(short, long)]
+    word: bool,
+
+    /// Make ^ and $ match at line breaks inside each chunk (if the
+    /// --delimiter doesn't split on them), too.
+    #[arg(long)]
+    multiline: bool,
+
+    /// Make . match newlines, too.
+    #[arg(long)]
+    dot_all: bool,
+
+    /// Match Unicode characters with classes like \w and \d (default).
+    #[arg(long, overrides_with = "ascii")]
+    unicode: bool,
+
+    /// Only match ASCII characters with classes like \w and \d.
+    #[arg(long, overrides_with = "unicode")]
+    ascii: bool,
+
+    /// Match against the whole input at once (instead of line by line), so
+    /// patterns can span lines.
+    #[arg(long, conflicts_with_all = ["delimiter", "lines", "where_match", "where_not", "between"])]
+    whole: bool,
+
+    /// Delimiter to separate "lines".
+    #[arg(short, long, value_name = "PATT", default_value = r#"\r?\n"#)]
+    delimiter: String,
+
+    /// Print something other than a newline between chunks.
+    #[arg(short, long, value_name = "NL")]
+    newline: Option<Option<String>>,
+
+    /// Input file (default is stdin).
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// Output file (default is stdout).
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Edit the FILEs in place, keeping backups with names ending in
+    /// SUFFIX (like -I=.bak), if given.
+    #[arg(short = 'I', long, value_name = "SUFFIX", num_args = 0..=1,
+        require_equals = true, conflicts_with = "output")]
+    in_place: Option<Option<String>>,
+
+    /// Print a unified diff of the changes instead of the altered text
+    /// (and don't edit any files).
+    #[arg(long)]
+    diff: bool,
+
+    /// Lines of context to show around each change in a --diff.
+    #[arg(
+        short = 'U',
+        long,
+        value_name = "N",
+        default_value = "3",
+        requires = "diff"
+    )]
+    context: usize,
+
+    /// Just check whether anything would change (and exit with status 1
+    /// if so), printing the names of the inputs that would.
+    #[arg(long)]
+    check: bool,
+}
+
+pub struct Opts {
+    pub rules: Vec<Rule>,
+    pub address: Address,
+    pub output_mode: OutputMode,
+    pub delimiter: String,
+    pub newline: Option<Vec<u8>>,
+    /// When editing files, put back the delimiters that were there
+    /// instead of `newline` (because it wasn't given explicitly).
+    pub keep_delimiters: bool,
+    /// Treat the whole input as one chunk.
+    pub whole: bool,
+    /// Show the changes as a diff with this many lines of context.
+    pub diff: Option<usize>,
+    /// Only report whether anything would change.
+    pub check: bool,
+}
+
+impl Opts {
+    pub fn new() -> Result<(Self, Target), FrErr> {
+        let matches = CliOpts::command().get_matches();
+        let clio = CliOpts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+        let defaults = Defaults {
+            match_mode: if clio.simple {
+                MatchMode::Verbatim
+            } else {
+                MatchMode::Regex
+            },
+            max: clio.max.unwrap_or(usize::MAX),
+            transform: clio.transform,
+            flags: Flags {
+                ignore_case: clio.ignore_case,
+                word: clio.word,
+                multi_line: clio.multiline,
+                dot_all: clio.dot_all,
+                ascii: clio.ascii,
+            },
+        };
+
+        // Rules from -e and -f, in the order they were given.
+        let mut given: Vec<(usize, Vec<Rule>)> = Vec::new();
+        let exprs = matches.indices_of("expression").into_iter().flatten();
+        for (idx, spec) in exprs.zip(clio.expression.iter()) {
+            let rule = Rule::parse(spec, defaults).map_err(|msg| FrErr::Rule {
+                origin: format!("-e '{}'", spec),
+                msg,
+            })?;
+            given.push((idx, vec![rule]));
+        }
+        let scripts = matches.indices_of("script").into_iter().flatten();
+        for (idx, path) in scripts.zip(clio.script.iter()) {
+            given.push((idx, Rule::read_file(path, defaults)?));
+        }
+        given.sort_by_key(|(idx, _)| *idx);
+
+        let mut files = clio.files;
+        let (rules, output_mode) = if given.is_empty() {
+            // Otherwise clap would have complained.
+            let pattern = clio.pattern.unwrap_or_default();
+            let (rule, output_mode) = match (clio.extract, clio.replace) {
+                (_, None) => (Rule::echo(&pattern, defaults)?, OutputMode::Extract),
+                (true, Some(repl)) => (Rule::new(&pattern, &repl, defaults)?, OutputMode::Extract),
+                (false, Some(repl)) => (Rule::new(&pattern, &repl, defaults)?, OutputMode::Replace),
+            };
+            (vec![rule], output_mode)
+        } else {
+            // With rules from -e or -f, all the arguments are files.
+            let mut args: Vec<PathBuf> = clio
+                .pattern
+                .into_iter()
+                .chain(clio.replace)
+                .map(PathBuf::from)
+                .collect();
+            args.append(&mut files);
+            files = args;
+
+            let rules = given.into_iter().flat_map(|(_, rules)| rules).collect();
+            let output_mode = match clio.extract {
+                true => OutputMode::Extract,
+                false => OutputMode::Replace,
+            };
+            (rules, output_mode)
+        };
+
+        if let Some(pbuf) = clio.input {
+            files.push(pbuf);
+        }
+        let target = match clio.in_place {
+            Some(_) if files.is_empty() => {
+                return Err(FrErr::Misc("--in-place requires at least one FILE".into()));
+            }
+            // An empty suffix would "back up" each file to itself.
+            Some(backup) => Target::InPlace {
+                files,
+                backup: backup.filter(|s| !s.is_empty()),
+            },
+            None => {
+                let output: Box<dyn Write> = match clio.output {
+                    Some(pbuf) => Box::new(File::create(&pbuf).map_err(FrErr::file(pbuf))?),
+                    None => Box::new(std::io::stdout().lock()),
+                };
+                Target::Stream { files, output }
+            }
+        };
+        // Whole-word matching is for the patterns being replaced, not the
+        // ones picking out which chunks to edit.
+        let flags = Flags {
+            word: false,
+            ..defaults.flags
+        };
+        let mut address = Address::default();
+        for s in clio.lines.iter() {
+            let range = LineRange::try_from(s.as_str()).map_err(|e| FrErr::Misc(e.into()))?;
+            address.lines.push(range);
+        }
+        for s in clio.where_match.iter() {
+            address.wheres.push(flags.regex(s)?);
+        }
+        for s in clio.where_not.iter() {
+            address.where_nots.push(flags.regex(s)?);
+        }
+        for pair in clio.between.chunks(2) {
+            if let [start, end] = pair {
+                address.betweens.push(Between {
+                    start: flags.regex(start)?,
+                    end: flags.regex(end)?,
+                });
+            }
+        }
+
+        let keep_delimiters = clio.newline.is_none();
+        let newline = match clio.newline {
+            // If the argument is absent, just use a newline sequence.
+            None => Some(Vec::from(NEWLINE)),
+            // If the argument is present but has no value, make it none.
+            Some(None) => None,
+            // If the argument is present and has a value, use that.
+            Some(Some(s)) => Some(Vec::from(s)),
+        };
+
+        let opts = Opts {
+            rules,
+            address,
+            output_mode,
+            delimiter: flags.inline(&clio.delimiter),
+            newline,
+            keep_delimiters,
+            whole: clio.whole,
+            diff: match clio.diff {
+                true => Some(clio.context),
+                false => None,
+            },
+            check: clio.check,
+        };
+
+        Ok((opts, target))
+    }
+}
@@ -0,0 +1,516 @@
+/*!
+Substitution rules.
+
+Each rule is a pattern to find and what to replace it with. Rules given
+with `-e` or read from script files (`-f`) are written like
+
+```text
+PATTERN=>REPLACEMENT
+```
+
+optionally preceded by options (separated by whitespace) that override
+the command-line ones for just that rule:
+
+  * `-s`, `--simple`: verbatim string matching
+  * `-r`, `--regex`: regex matching
+  * `-m N`, `--max N`: at most `N` replacements per chunk
+  * `-i`, `--ignore-case`: case-insensitive matching
+  * `-w`, `--word`: only match whole words
+  * `-t`, `--transform`: case conversion and filters in the replacement
+
+A pattern that itself starts with `-` can follow `--`. In script files,
+blank lines and lines starting with `#` are ignored.
+*/
+use std::{borrow::Cow, ops::Range, path::Path};
+
+use regex::bytes::{Captures, NoExpand, Regex, RegexBuilder};
+use regex_syntax::ParserBuilder;
+
+use crate::{err::FrErr, opt::MatchMode, template::Template};
+
+/// Separates the pattern from the replacement in a written rule.
+static ARROW: &str = "=>";
+
+/// How patterns are matched (besides regex vs. verbatim).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Flags {
+    pub ignore_case: bool,
+    /// Only match whole words.
+    pub word: bool,
+    /// `^` and `$` match at the start and end of lines.
+    pub multi_line: bool,
+    /// `.` matches newlines, too.
+    pub dot_all: bool,
+    /// Classes like `\w` and `\d` (and `.`) only match ASCII.
+    pub ascii: bool,
+}
+
+impl Flags {
+    /// `pattern`, made to only match whole words if necessary.
+    fn pattern<'p>(&self, pattern: &'p str) -> Cow<'p, str> {
+        match self.word {
+            true => Cow::Owned(format!(r"\b(?:{})\b", pattern)),
+            false => Cow::Borrowed(pattern),
+        }
+    }
+
+    /// Compile the regex `pattern` with these settings.
+    pub fn regex(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(&self.pattern(pattern))
+            .case_insensitive(self.ignore_case)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_all)
+            .unicode(!self.ascii)
+            .build()
+    }
+
+    /// The most bytes a match of the regex `pattern` could be, if it's
+    /// limited.
+    fn max_len(&self, pattern: &str) -> Option<usize> {
+        ParserBuilder::new()
+            .case_insensitive(self.ignore_case)
+            .multi_line(self.multi_line)
+            .dot_matches_new_line(self.dot_all)
+            .unicode(!self.ascii)
+            .utf8(false)
+            .build()
+            .parse(&self.pattern(pattern))
+            .ok()?
+            .properties()
+            .maximum_len()
+    }
+
+    /// Add these settings to the front of `pattern` as inline flags, for
+    /// regexes that get compiled somewhere else. (Whole-word and
+    /// multi-line matching don't make sense there, so they're left out.)
+    pub fn inline(&self, pattern: &str) -> String {
+        let mut flags = String::new();
+        if self.ignore_case {
+            flags.push('i');
+        }
+        if self.dot_all {
+            flags.push('s');
+        }
+        if self.ascii {
+            flags.push_str("-u");
+        }
+
+        match flags.is_empty() {
+            true => pattern.to_owned(),
+            false => format!("(?{}){}", flags, pattern),
+        }
+    }
+}
+
+/// Settings for any rule that doesn't specify its own.
+#[derive(Clone, Copy, Debug)]
+pub struct Defaults {
+    pub match_mode: MatchMode,
+    pub max: usize,
+    pub flags: Flags,
+    /// Expand replacements as [`Template`]s.
+    pub transform: bool,
+}
+
+enum Matcher {
+    Regex(Regex),
+    Verbatim(Vec<u8>),
+    /// Verbatim matching that needs a regex (to ignore case or match
+    /// whole words); the replacement is still used as is.
+    Literal(Regex),
+}
+
+pub struct Rule {
+    matcher: Matcher,
+    replace: Vec<u8>,
+    /// How to expand `replace`, if it needs more than `Captures::expand`.
+    template: Option<Template>,
+    /// Each match is replaced with the text it matched (which, when
+    /// ignoring case or matching words, may not be the pattern itself).
+    echo: bool,
+    pub max: usize,
+    /// The longest a match could be, if there's a limit.
+    pub max_len: Option<usize>,
+}
+
+/// A match found by [`Rule::find_at`].
+pub struct Found<'t> {
+    pub span: Range<usize>,
+    text: &'t [u8],
+    caps: Option<Captures<'t>>,
+}
+
+fn find_subslice<T>(haystack: &[T], needle: &[T]) -> Option<usize>
+where
+    T: PartialEq,
+{
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+
+    for (n, w) in haystack.windows(needle.len()).enumerate() {
+        if w == needle {
+            return Some(n);
+        }
+    }
+
+    None
+}
+
+/// Split the first whitespace-delimited word off the front of `s`.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(n) => (&s[..n], s[n..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+fn parse_max(s: &str) -> Result<usize, String> {
+    s.parse()
+        .map_err(|_| format!("invalid maximum number of replacements \"{}\"", s))
+}
+
+impl Rule {
+    pub fn new(pattern: &str, replace: &str, settings: Defaults) -> Result<Rule, FrErr> {
+        let Defaults {
+            match_mode,
+            max,
+            flags,
+            transform,
+        } = settings;
+        let (matcher, max_len) = match match_mode {
+            MatchMode::Regex => (
+                Matcher::Regex(flags.regex(pattern)?),
+                flags.max_len(pattern),
+            ),
+            MatchMode::Verbatim if flags.ignore_case || flags.word => {
+                let escaped = regex::escape(pattern);
+                (
+                    Matcher::Literal(flags.regex(&escaped)?),
+                    flags.max_len(&escaped),
+                )
+            }
+            MatchMode::Verbatim => (Matcher::Verbatim(Vec::from(pattern)), Some(pattern.len())),
+        };
+
+        // Replacements are only templates when matching regexes, and only
+        // when asked for (so existing backslashes keep meaning backslash).
+        let template = match (&matcher, transform) {
+            (Matcher::Regex(_), true) => Some(Template::parse(replace).map_err(FrErr::Template)?),
+            _ => None,
+        };
+
+        Ok(Rule {
+            matcher,
+            replace: Vec::from(replace),
+            template,
+            echo: false,
+            max,
+            max_len,
+        })
+    }
+
+    /// A rule that replaces each match of `pattern` with what it matched;
+    /// for extracting matches when there's no replacement.
+    pub fn echo(pattern: &str, settings: Defaults) -> Result<Rule, FrErr> {
+        let replace = match settings.match_mode {
+            MatchMode::Regex => "$0",
+            MatchMode::Verbatim => pattern,
+        };
+        let mut rule = Rule::new(pattern, replace, settings)?;
+        rule.echo = true;
+        Ok(rule)
+    }
+
+    /// Parse a rule written as `[OPTIONS] PATTERN=>REPLACEMENT`.
+    pub fn parse(spec: &str, defaults: Defaults) -> Result<Rule, String> {
+        let mut settings = defaults;
+
+        let mut rest = spec;
+        while rest.starts_with('-') {
+            let (word, after) = split_word(rest);
+            rest = after;
+            match word {
+                "--" => break,
+                "-s" | "--simple" => settings.match_mode = MatchMode::Verbatim,
+                "-r" | "--regex" => settings.match_mode = MatchMode::Regex,
+                "-i" | "--ignore-case" => settings.flags.ignore_case = true,
+                "-w" | "--word" => settings.flags.word = true,
+                "-t" | "--transform" => settings.transform = true,
+                "-m" | "--max" => {
+                    let (n, after) = split_word(rest);
+                    settings.max = parse_max(n)?;
+                    rest = after;
+                }
+                _ => {
+                    if let Some(n) = word.strip_prefix("--max=") {
+                        settings.max = parse_max(n)?;
+                    } else if let Some(n) = word.strip_prefix("-m") {
+                        settings.max = parse_max(n)?;
+                    } else {
+                        return Err(format!("unknown rule option \"{}\"", word));
+                    }
+                }
+            }
+        }
+
+        let (pattern, replace) = rest
+            .split_once(ARROW)
+            .ok_or_else(|| format!("expected PATTERN{}REPLACEMENT", ARROW))?;
+        Rule::new(pattern, replace, settings).map_err(|e| match e {
+            FrErr::Regex(e) => format!("{}", &e),
+            FrErr::Template(msg) => msg,
+            e => format!("{}", &e),
+        })
+    }
+
+    /// Read the rules from the script file at `path`.
+    pub fn read_file(path: &Path, defaults: Defaults) -> Result<Vec<Rule>, FrErr> {
+        let text = std::fs::read_to_string(path).map_err(FrErr::file(path))?;
+
+        let mut rules = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = Rule::parse(line, defaults).map_err(|msg| FrErr::Rule {
+                origin: format!("{}, line {}", path.display(), n + 1),
+                msg,
+            })?;
+            rules.push(rule);
+        }
+
+        Ok(rules)
+    }
+
+    /// Replace (up to the maximum number of) matches in `text`.
+    pub fn replace<'a>(&self, text: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.matcher {
+            Matcher::Regex(ref re) => match self.template {
+                None => re.replacen(text, self.max, self.replace.as_slice()),
+                Some(ref template) => {
+                    let mut buff: Vec<u8> = Vec::new();
+                    let mut last: usize = 0;
+                    let mut n_replaced: usize = 0;
+                    for cap in re.captures_iter(text).take(self.max) {
+                        // Group 0 is always the whole match.
+                        let whole = cap.get(0).unwrap();
+                        buff.extend_from_slice(&text[last..whole.start()]);
+                        template.expand(&cap, &mut buff);
+                        last = whole.end();
+                        n_replaced += 1;
+                    }
+
+                    if n_replaced == 0 {
+                        return Cow::Borrowed(text);
+                    }
+                    buff.extend_from_slice(&text[last..]);
+                    Cow::Owned(buff)
+                }
+            },
+            Matcher::Literal(_) if self.echo => Cow::Borrowed(text),
+            Matcher::Literal(ref re) => re.replacen(text, self.max, NoExpand(&self.replace)),
+            Matcher::Verbatim(ref patt) => {
+                let mut subslice = text;
+                let mut buff: Vec<u8> = Vec::new();
+                let mut n_replaced: usize = 0;
+
+                while n_replaced < self.max {
+                    if let Some(n) = find_subslice(subslice, patt) {
+                        buff.extend_from_slice(&subslice[..n]);
+                        buff.extend_from_slice(&self.replace);
+                        n_replaced += 1;
+                        subslice = &subslice[n + patt.len()..];
+                    } else {
+                        break;
+                    }
+                }
+
+                if n_replaced == 0 {
+                    return Cow::Borrowed(text);
+                }
+                buff.extend_from_slice(subslice);
+                Cow::Owned(buff)
+            }
+        }
+    }
+
+    /**
+    Find the first match in `text` that starts at or after `start`. What
+    comes before `start` still counts for things like `^` and `\b`.
+    */
+    pub fn find_at<'t>(&self, text: &'t [u8], start: usize) -> Option<Found<'t>> {
+        match self.matcher {
+            Matcher::Regex(ref re) => {
+                let caps = re.captures_at(text, start)?;
+                Some(Found {
+                    span: caps.get(0)?.range(),
+                    text,
+                    caps: Some(caps),
+                })
+            }
+            Matcher::Literal(ref re) => Some(Found {
+                span: re.find_at(text, start)?.range(),
+                text,
+                caps: None,
+            }),
+            Matcher::Verbatim(ref patt) => {
+                let n = start + find_subslice(&text[start..], patt)?;
+                Some(Found {
+                    span: n..(n + patt.len()),
+                    text,
+                    caps: None,
+                })
+            }
+        }
+    }
+
+    /// Write the replacement for `found` to `buff`.
+    pub fn expand(&self, found: &Found, buff: &mut Vec<u8>) {
+        match (&found.caps, &self.template) {
+            (Some(caps), Some(template)) => template.expand(caps, buff),
+            (Some(caps), None) => caps.expand(&self.replace, buff),
+            (None, _) if self.echo => buff.extend_from_slice(&found.text[found.span.clone()]),
+            (None, _) => buff.extend_from_slice(&self.replace),
+        }
+    }
+
+    /// Write (the replacement for each of up to the maximum number of)
+    /// matches in `text` to `buff`.
+    pub fn extract(&self, text: &[u8], buff: &mut Vec<u8>) {
+        match self.matcher {
+            Matcher::Regex(ref re) => {
+                for cap in re.captures_iter(text).take(self.max) {
+                    match self.template {
+                        Some(ref template) => template.expand(&cap, buff),
+                        None => cap.expand(&self.replace, buff),
+                    }
+                }
+            }
+            Matcher::Literal(ref re) => {
+                for m in re.find_iter(text).take(self.max) {
+                    match self.echo {
+                        true => buff.extend_from_slice(m.as_bytes()),
+                        false => buff.extend_from_slice(&self.replace),
+                    }
+                }
+            }
+            Matcher::Verbatim(ref patt) => {
+                let mut subslice = text;
+                let mut n_replaced: usize = 0;
+
+                while n_replaced < self.max {
+                    if let Some(n) = find_subslice(subslice, patt) {
+                        buff.extend_from_slice(&self.replace);
+                        n_replaced += 1;
+                        subslice = &subslice[n + patt.len()..];
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Defaults {
+        Defaults {
+            match_mode: MatchMode::Regex,
+            max: usize::MAX,
+            flags: Flags::default(),
+            transform: false,
+        }
+    }
+
+    /// Parse `spec` and apply it to `text`.
+    fn apply(spec: &str, text: &str) -> String {
+        let rule = Rule::parse(spec, defaults()).unwrap();
+        String::from_utf8(rule.replace(text.as_bytes()).into_owned()).unwrap()
+    }
+
+    fn parse_err(spec: &str) -> String {
+        Rule::parse(spec, defaults()).err().unwrap()
+    }
+
+    #[test]
+    fn plain() {
+        assert_eq!(apply("a+=>b", "caaat"), "cbt");
+        assert_eq!(apply(r"(\w+)@(\w+)=>$2 at $1", "me@here"), "here at me");
+        // Only the first arrow separates.
+        assert_eq!(apply("a=>=>", "cat"), "c=>t");
+        assert_eq!(apply("x=>", "xyx"), "y");
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(apply("-s a+=>b", "a+aa"), "baa");
+        assert_eq!(apply("-s -r a+=>b", "a+aa"), "b+b");
+        assert_eq!(apply("-i CAT=>dog", "Cat cat"), "dog dog");
+        assert_eq!(
+            apply("--ignore-case --simple C.T=>dog", "c.t cat"),
+            "dog cat"
+        );
+        assert_eq!(apply("-w cat=>dog", "cat concat"), "dog concat");
+        assert_eq!(apply("-t (\\w+)=>\\U$1", "loud"), "LOUD");
+        assert_eq!(apply("(\\w+)=>\\U$1", "loud"), "\\Uloud");
+    }
+
+    /// Extract what `pattern` matches in `text`, as with no replacement.
+    fn echo(pattern: &str, settings: Defaults, text: &str) -> String {
+        let rule = Rule::echo(pattern, settings).unwrap();
+        let mut buff = Vec::new();
+        rule.extract(text.as_bytes(), &mut buff);
+
+        // Matching a piece at a time (as with --whole) should agree.
+        let mut found = Vec::new();
+        let mut start = 0;
+        while let Some(f) = rule.find_at(text.as_bytes(), start) {
+            rule.expand(&f, &mut found);
+            start = f.span.end.max(f.span.start + 1);
+        }
+        assert_eq!(found, buff);
+
+        String::from_utf8(buff).unwrap()
+    }
+
+    #[test]
+    fn echo_matches() {
+        let mut settings = defaults();
+        assert_eq!(echo("f.o", settings, "FOO bar foo"), "foo");
+        settings.flags.ignore_case = true;
+        assert_eq!(echo("f.o", settings, "FOO bar foo"), "FOOfoo");
+        settings.match_mode = MatchMode::Verbatim;
+        assert_eq!(echo("foo", settings, "FOO bar foo"), "FOOfoo");
+        settings.flags.ignore_case = false;
+        assert_eq!(echo("foo", settings, "FOO bar foo"), "foo");
+        settings.flags.word = true;
+        assert_eq!(echo("foo", settings, "food foo"), "foo");
+    }
+
+    #[test]
+    fn max() {
+        assert_eq!(apply("-m 1 a=>b", "aaa"), "baa");
+        assert_eq!(apply("-m2 a=>b", "aaa"), "bba");
+        assert_eq!(apply("--max 1 a=>b", "aaa"), "baa");
+        assert_eq!(apply("--max=2 a=>b", "aaa"), "bba");
+        assert!(parse_err("-m x a=>b").contains("\"x\""));
+    }
+
+    #[test]
+    fn dashes() {
+        assert_eq!(apply("-- -x=>y", "a-x"), "ay");
+        assert_eq!(apply("-s -- -x=>y", "a-x"), "ay");
+        assert_eq!(parse_err("-x=>y"), "unknown rule option \"-x=>y\"");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_err("abc"), "expected PATTERN=>REPLACEMENT");
+        assert!(!parse_err("(=>x").is_empty());
+        assert_eq!(parse_err("-t a=>${0:nope}"), "unknown filter \"nope\"");
+    }
+}
@@ -0,0 +1,458 @@
+/*!
+Replacement templates, used with `-t`/`--transform`.
+
+On top of the `$1`/`${name}` syntax of
+[`Captures::expand`](https://docs.rs/regex/latest/regex/bytes/struct.Captures.html#method.expand),
+a replacement can change the case of what it produces:
+
+  * `\U` uppercases everything after it, until `\E` (or the end),
+  * `\L` lowercases everything after it, until `\E` (or the end),
+  * `\u` uppercases just the next character, and `\l` lowercases it,
+  * `\\` is a literal backslash,
+
+and can pass groups through filters, like `${1:upper}` or
+`${name:trim:snake}` (applied left to right):
+
+  * `upper`, `lower`: change the case of the whole thing
+  * `snake`, `kebab`, `shout`, `camel`, `pascal`: split it into words and
+    join them back together `like_this`, `like-this`, `LIKE_THIS`,
+    `likeThis`, or `LikeThis`
+  * `trim`: remove whitespace from both ends
+  * `len`: replace it with its length (in characters)
+  * `urlencode`: percent-encode everything but letters, digits, and
+    `-._~`
+
+Any other backslash is just a backslash.
+*/
+use std::borrow::Cow;
+
+use regex::bytes::Captures;
+
+#[derive(Clone, Copy, Debug)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Filter {
+    Upper,
+    Lower,
+    Snake,
+    Kebab,
+    Shout,
+    Camel,
+    Pascal,
+    Trim,
+    Len,
+    UrlEncode,
+}
+
+#[derive(Debug)]
+enum Group {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug)]
+enum Part {
+    Text(Vec<u8>),
+    Group(Group, Vec<Filter>),
+    /// `\U`, `\L`, or (`None`) `\E`
+    Case(Option<Case>),
+    /// `\u` or `\l`
+    Next(Case),
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b == b'_' || b.is_ascii_alphanumeric()
+}
+
+impl Group {
+    fn new(name: &str) -> Group {
+        match name.parse() {
+            Ok(n) => Group::Index(n),
+            Err(_) => Group::Name(name.to_owned()),
+        }
+    }
+}
+
+impl Case {
+    fn apply(self, text: &[u8]) -> Vec<u8> {
+        match (std::str::from_utf8(text), self) {
+            (Ok(s), Case::Upper) => s.to_uppercase().into_bytes(),
+            (Ok(s), Case::Lower) => s.to_lowercase().into_bytes(),
+            (Err(_), Case::Upper) => text.to_ascii_uppercase(),
+            (Err(_), Case::Lower) => text.to_ascii_lowercase(),
+        }
+    }
+}
+
+/// Split `s` into words: runs of letters and digits, also broken where
+/// a lowercase letter (or digit) is followed by an uppercase one, or
+/// before the last of a run of capitals that's followed by a lowercase
+/// letter (so `HTTPServer` is `HTTP` and `Server`).
+fn words(s: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let mut words = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (n, &(i, c)) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if let Some(st) = start.take() {
+                words.push(&s[st..i]);
+            }
+            continue;
+        }
+        if let (Some(st), Some(&(_, prev))) = (start, n.checked_sub(1).map(|p| &chars[p])) {
+            let next = chars.get(n + 1).map(|&(_, c)| c);
+            let hump = c.is_uppercase()
+                && (prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && next.map_or(false, char::is_lowercase)));
+            if hump {
+                words.push(&s[st..i]);
+                start = Some(i);
+            }
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(st) = start {
+        words.push(&s[st..]);
+    }
+
+    words
+}
+
+/// `word` with its first character uppercase and the rest lowercase.
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c
+            .to_uppercase()
+            .chain(chars.as_str().to_lowercase().chars())
+            .collect(),
+        None => String::new(),
+    }
+}
+
+impl Filter {
+    fn parse(name: &str) -> Result<Filter, String> {
+        let f = match name {
+            "upper" => Filter::Upper,
+            "lower" => Filter::Lower,
+            "snake" => Filter::Snake,
+            "kebab" => Filter::Kebab,
+            "shout" => Filter::Shout,
+            "camel" => Filter::Camel,
+            "pascal" => Filter::Pascal,
+            "trim" => Filter::Trim,
+            "len" => Filter::Len,
+            "urlencode" => Filter::UrlEncode,
+            _ => return Err(format!("unknown filter \"{}\"", name)),
+        };
+        Ok(f)
+    }
+
+    fn apply(self, text: &[u8]) -> Vec<u8> {
+        match self {
+            Filter::Upper => Case::Upper.apply(text),
+            Filter::Lower => Case::Lower.apply(text),
+            Filter::Trim => match std::str::from_utf8(text) {
+                Ok(s) => Vec::from(s.trim()),
+                Err(_) => {
+                    let start = text.iter().position(|b| !b.is_ascii_whitespace());
+                    let end = text.iter().rposition(|b| !b.is_ascii_whitespace());
+                    match (start, end) {
+                        (Some(a), Some(b)) => Vec::from(&text[a..=b]),
+                        _ => Vec::new(),
+                    }
+                }
+            },
+            Filter::Len => {
+                let n = match std::str::from_utf8(text) {
+                    Ok(s) => s.chars().count(),
+                    Err(_) => text.len(),
+                };
+                n.to_string().into_bytes()
+            }
+            Filter::UrlEncode => {
+                let mut out = Vec::with_capacity(text.len());
+                for &b in text.iter() {
+                    if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                        out.push(b);
+                    } else {
+                        out.extend_from_slice(format!("%{:02X}", b).as_bytes());
+                    }
+                }
+                out
+            }
+            Filter::Snake | Filter::Kebab | Filter::Shout | Filter::Camel | Filter::Pascal => {
+                let s = String::from_utf8_lossy(text);
+                let words = words(&s);
+                let joined = match self {
+                    Filter::Snake => words
+                        .iter()
+                        .map(|w| w.to_lowercase())
+                        .collect::<Vec<_>>()
+                        .join("_"),
+                    Filter::Kebab => words
+                        .iter()
+                        .map(|w| w.to_lowercase())
+                        .collect::<Vec<_>>()
+                        .join("-"),
+                    Filter::Shout => words
+                        .iter()
+                        .map(|w| w.to_uppercase())
+                        .collect::<Vec<_>>()
+                        .join("_"),
+                    Filter::Camel => words
+                        .iter()
+                        .enumerate()
+                        .map(|(n, w)| match n {
+                            0 => w.to_lowercase(),
+                            _ => capitalize(w),
+                        })
+                        .collect(),
+                    _ => words.iter().map(|w| capitalize(w)).collect(),
+                };
+                joined.into_bytes()
+            }
+        }
+    }
+}
+
+/// Writes text to a buffer, changing its case as requested.
+struct Cased<'b> {
+    buff: &'b mut Vec<u8>,
+    mode: Option<Case>,
+    next: Option<Case>,
+}
+
+impl<'b> Cased<'b> {
+    fn push(&mut self, text: &[u8]) {
+        if text.is_empty() {
+            return;
+        }
+        let mut text = text;
+        if let Some(case) = self.next.take() {
+            // The first character, if this is UTF-8; otherwise the first byte.
+            let n = match std::str::from_utf8(text) {
+                Ok(s) => s.chars().next().map_or(1, char::len_utf8),
+                Err(_) => 1,
+            };
+            self.buff.extend_from_slice(&case.apply(&text[..n]));
+            text = &text[n..];
+        }
+        match self.mode {
+            Some(case) => self.buff.extend_from_slice(&case.apply(text)),
+            None => self.buff.extend_from_slice(text),
+        }
+    }
+}
+
+/// A parsed replacement.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(replace: &str) -> Result<Template, String> {
+        let bytes = replace.as_bytes();
+        let mut parts: Vec<Part> = Vec::new();
+        let mut text: Vec<u8> = Vec::new();
+        let mut n = 0;
+
+        while n < bytes.len() {
+            let part = match (bytes[n], bytes.get(n + 1)) {
+                (b'\\', Some(&c)) => {
+                    let part = match c {
+                        b'U' => Part::Case(Some(Case::Upper)),
+                        b'L' => Part::Case(Some(Case::Lower)),
+                        b'E' => Part::Case(None),
+                        b'u' => Part::Next(Case::Upper),
+                        b'l' => Part::Next(Case::Lower),
+                        b'\\' => Part::Text(vec![b'\\']),
+                        _ => {
+                            text.push(b'\\');
+                            n += 1;
+                            continue;
+                        }
+                    };
+                    n += 2;
+                    part
+                }
+                (b'$', Some(b'$')) => {
+                    n += 2;
+                    Part::Text(vec![b'$'])
+                }
+                (b'$', Some(b'{')) => {
+                    let close = replace[n..].find('}').map(|m| n + m);
+                    let mut fields = close.map(|m| replace[(n + 2)..m].split(':'));
+                    let name = fields.as_mut().and_then(|f| f.next()).unwrap_or_default();
+                    if name.is_empty() || !name.bytes().all(is_name_byte) {
+                        // Not a group; just a dollar sign.
+                        text.push(b'$');
+                        n += 1;
+                        continue;
+                    }
+                    let filters = fields
+                        .into_iter()
+                        .flatten()
+                        .map(Filter::parse)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    n = close.unwrap_or(n) + 1;
+                    Part::Group(Group::new(name), filters)
+                }
+                (b'$', _) => {
+                    let len = bytes[(n + 1)..]
+                        .iter()
+                        .position(|&b| !is_name_byte(b))
+                        .unwrap_or(bytes.len() - n - 1);
+                    if len == 0 {
+                        text.push(b'$');
+                        n += 1;
+                        continue;
+                    }
+                    let name = &replace[(n + 1)..(n + 1 + len)];
+                    n += 1 + len;
+                    Part::Group(Group::new(name), Vec::new())
+                }
+                (b, _) => {
+                    text.push(b);
+                    n += 1;
+                    continue;
+                }
+            };
+
+            match part {
+                Part::Text(t) => text.extend_from_slice(&t),
+                part => {
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Write the replacement for the match `caps` to `buff`.
+    pub fn expand(&self, caps: &Captures, buff: &mut Vec<u8>) {
+        let mut out = Cased {
+            buff,
+            mode: None,
+            next: None,
+        };
+
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(t) => out.push(t),
+                Part::Group(group, filters) => {
+                    let m = match group {
+                        Group::Index(n) => caps.get(*n),
+                        Group::Name(name) => caps.name(name),
+                    };
+                    let mut text: Cow<[u8]> = Cow::Borrowed(m.map_or(&[][..], |m| m.as_bytes()));
+                    for f in filters.iter() {
+                        text = Cow::Owned(f.apply(&text));
+                    }
+                    out.push(&text);
+                }
+                Part::Case(case) => out.mode = *case,
+                Part::Next(case) => out.next = Some(*case),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::bytes::Regex;
+
+    /// Replace the first match of `pattern` in `text` using `replace`.
+    fn expand(pattern: &str, replace: &str, text: &str) -> String {
+        let caps = Regex::new(pattern)
+            .unwrap()
+            .captures(text.as_bytes())
+            .unwrap();
+        let mut buff = Vec::new();
+        Template::parse(replace).unwrap().expand(&caps, &mut buff);
+        String::from_utf8(buff).unwrap()
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(expand(r"(\w+) (\w+)", "$2 $1", "one two"), "two one");
+        assert_eq!(expand(r"(?P<w>\w+)", "<${w}>", "word"), "<word>");
+        assert_eq!(expand(r"(\w+)", "${1}s", "cat"), "cats");
+        assert_eq!(expand(r"(\w+)", "$$1 costs $", "cat"), "$1 costs $");
+        assert_eq!(expand(r"(\w+)", "$9", "cat"), "");
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(expand(r"(\w+) (\w+)", r"\U$1\E $2", "big deal"), "BIG deal");
+        assert_eq!(expand(r"(\w+)", r"\L$1", "QUIET"), "quiet");
+        assert_eq!(expand(r"(\w+)", r"\u$1", "name"), "Name");
+        assert_eq!(expand(r"(\w+)", r"\l$1", "NAME"), "nAME");
+        assert_eq!(expand(r"(\w+)", r"\U\l$1", "name"), "nAME");
+        assert_eq!(expand(r"(\w+)", r"\U$1 and more", "big"), "BIG AND MORE");
+        assert_eq!(expand(r"(\w+)", r"\u$1", "élan"), "Élan");
+    }
+
+    #[test]
+    fn backslashes() {
+        assert_eq!(expand(r"(\w+)", r"C:\\$1", "Users"), r"C:\Users");
+        assert_eq!(expand(r"(\w+)", r"a\tb\$1", "x"), r"a\tb\x");
+        assert_eq!(expand(r"(\w+)", r"end\", "x"), r"end\");
+    }
+
+    #[test]
+    fn filters() {
+        let id = r"(\S+)";
+        assert_eq!(
+            expand(id, "${1:snake}", "HTTPServerError"),
+            "http_server_error"
+        );
+        assert_eq!(expand(id, "${1:kebab}", "someValue2Go"), "some-value2-go");
+        assert_eq!(expand(id, "${1:shout}", "some-name"), "SOME_NAME");
+        assert_eq!(expand(id, "${1:camel}", "some_name"), "someName");
+        assert_eq!(expand(id, "${1:pascal}", "some_name"), "SomeName");
+        assert_eq!(expand(id, "${1:upper}", "ab"), "AB");
+        assert_eq!(expand(id, "${1:lower}", "AB"), "ab");
+        assert_eq!(expand(id, "${1:len}", "naïve"), "5");
+        assert_eq!(expand("(.+)", "${1:urlencode}", "a/b c"), "a%2Fb%20c");
+        assert_eq!(expand(r"\[(.*)\]", "${1:trim:upper}", "[ x y ]"), "X Y");
+    }
+
+    #[test]
+    fn not_groups() {
+        assert_eq!(expand(r"(\w+)", "${}", "x"), "${}");
+        assert_eq!(expand(r"(\w+)", "${1", "x"), "${1");
+        assert_eq!(expand(r"(\w+)", "$-", "x"), "$-");
+    }
+
+    #[test]
+    fn bad_filter() {
+        assert_eq!(
+            Template::parse("${1:loud}").unwrap_err(),
+            "unknown filter \"loud\""
+        );
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(super::words("HTTPServer"), ["HTTP", "Server"]);
+        assert_eq!(super::words("parseURL2x"), ["parse", "URL2x"]);
+        assert_eq!(super::words("  a--b  "), ["a", "b"]);
+        assert!(super::words("").is_empty());
+    }
+}
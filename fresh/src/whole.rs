@@ -0,0 +1,287 @@
+/*!
+Matching against the whole input at once (`--whole`), so patterns can
+span lines.
+
+Each rule works on the output of the one before, a block at a time. If
+the longest match a rule's pattern could make is limited, text that's far
+enough behind the end of what's been read can't be part of any match that
+hasn't been found yet, so it's passed along and forgotten. Otherwise (as
+with something like `a.*b`), the rule has to wait for the whole input.
+*/
+use std::{
+    borrow::Cow,
+    io::{ErrorKind, Read, Write},
+};
+
+use crate::{
+    err::FrErr,
+    opt::{Opts, OutputMode},
+    rule::Rule,
+};
+
+/// How much input to read at a time.
+const BLOCK: usize = 64 * 1024;
+/// Rules that could match more than this much text are applied to the
+/// whole input at once instead.
+const MAX_WINDOW: usize = 1024 * 1024;
+/// How much already-handled text to keep around, so that things like `^`
+/// and `\b` know what came before. (A UTF-8 character is at most 4 bytes.)
+const LOOK: usize = 4;
+
+/// One rule's progress through the text.
+struct Stage<'a> {
+    rule: &'a Rule,
+    /// How far past where a match starts it could reach (the longest a
+    /// match could be, plus some lookahead), if streaming.
+    reach: Option<usize>,
+    buff: Vec<u8>,
+    /// Everything in `buff` before this has been dealt with.
+    done: usize,
+    /// Where the last match ended (to avoid an empty match right after it).
+    last_end: Option<usize>,
+    count: usize,
+}
+
+impl<'a> Stage<'a> {
+    fn new(rule: &'a Rule) -> Stage<'a> {
+        let reach = rule.max_len.filter(|&n| n <= MAX_WINDOW).map(|n| n + LOOK);
+
+        Stage {
+            rule,
+            reach,
+            buff: Vec::new(),
+            done: 0,
+            last_end: None,
+            count: 0,
+        }
+    }
+
+    /**
+    Deal with the matches that start before `limit` (or at it, if this is
+    the end of the input), and whatever text is between them, writing the
+    result to `out`.
+    */
+    fn scan(&mut self, limit: usize, end: bool, mode: OutputMode, out: &mut Vec<u8>) {
+        let mut pos = self.done;
+        while self.count < self.rule.max && pos <= self.buff.len() {
+            let found = match self.rule.find_at(&self.buff, pos) {
+                Some(found) => found,
+                None => break,
+            };
+            let span = found.span.clone();
+            if span.start > limit || (span.start == limit && !end) {
+                break;
+            }
+            // The same as the regex crate does when it iterates.
+            if span.is_empty() && self.last_end == Some(span.end) {
+                pos = span.start + 1;
+                continue;
+            }
+
+            if let OutputMode::Replace = mode {
+                out.extend_from_slice(&self.buff[self.done..span.start]);
+            }
+            self.rule.expand(&found, out);
+            self.done = span.end;
+            self.last_end = Some(span.end);
+            self.count += 1;
+            pos = span.end;
+        }
+
+        // Anything before `limit` that isn't part of a match won't ever be.
+        let limit = match self.count < self.rule.max {
+            true => limit,
+            false => self.buff.len(),
+        };
+        if self.done < limit {
+            if let OutputMode::Replace = mode {
+                out.extend_from_slice(&self.buff[self.done..limit]);
+            }
+            self.done = limit;
+        }
+    }
+
+    /// Add `text` to what this rule has to work on, writing whatever it's
+    /// done with to `out`.
+    fn push(&mut self, text: &[u8], mode: OutputMode, out: &mut Vec<u8>) {
+        self.buff.extend_from_slice(text);
+        let reach = match self.reach {
+            Some(n) => n,
+            None => return,
+        };
+        if self.buff.len() < self.done + reach {
+            return;
+        }
+
+        self.scan(self.buff.len() - reach, false, mode, out);
+
+        let forget = self.done.saturating_sub(LOOK);
+        if forget > 0 {
+            self.buff.drain(..forget);
+            self.done -= forget;
+            self.last_end = self.last_end.and_then(|n| n.checked_sub(forget));
+        }
+    }
+
+    /// Finish up at the end of the input.
+    fn finish(&mut self, mode: OutputMode, out: &mut Vec<u8>) {
+        match self.reach {
+            Some(_) => self.scan(self.buff.len(), true, mode, out),
+            None => match mode {
+                OutputMode::Replace => out.extend_from_slice(&self.rule.replace(&self.buff)),
+                OutputMode::Extract => self.rule.extract(&self.buff, out),
+            },
+        }
+    }
+}
+
+/// Pass `text` through all the `stages`; `end` says whether it's the end
+/// of the input. Returns the result.
+fn feed<'t>(stages: &mut [Stage], text: &'t [u8], end: bool, mode: OutputMode) -> Cow<'t, [u8]> {
+    let mut text = Cow::Borrowed(text);
+    for stage in stages.iter_mut() {
+        let mut out: Vec<u8> = Vec::new();
+        stage.push(&text, mode, &mut out);
+        if end {
+            stage.finish(mode, &mut out);
+        }
+        text = Cow::Owned(out);
+    }
+    text
+}
+
+/**
+Like [`crate::transform`], but treating the whole input as one chunk. When
+extracting, a newline is printed at the end (if anything was extracted);
+otherwise the text is written exactly as altered.
+*/
+pub fn transform<R: Read>(opts: &Opts, mut input: R, output: &mut dyn Write) -> Result<(), FrErr> {
+    let mut stages: Vec<Stage> = opts.rules.iter().map(Stage::new).collect();
+    let mut block = vec![0u8; BLOCK];
+    let mut wrote = false;
+
+    loop {
+        let n = match input.read(&mut block) {
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let text = feed(&mut stages, &block[..n], n == 0, opts.output_mode);
+        output.write_all(&text)?;
+        wrote |= !text.is_empty();
+        if n == 0 {
+            break;
+        }
+    }
+
+    if let OutputMode::Extract = opts.output_mode {
+        if let (true, Some(nl_bytes)) = (wrote, &opts.newline) {
+            output.write_all(nl_bytes)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        opt::MatchMode,
+        rule::{Defaults, Flags},
+    };
+
+    fn rule(pattern: &str, replace: &str, max: usize, multi_line: bool) -> Rule {
+        let defaults = Defaults {
+            match_mode: MatchMode::Regex,
+            max,
+            flags: Flags {
+                multi_line,
+                ..Flags::default()
+            },
+            transform: false,
+        };
+        Rule::new(pattern, replace, defaults).unwrap()
+    }
+
+    /// Run `text` through `rules` in pieces of `size` bytes.
+    fn stream(rules: &[Rule], text: &[u8], size: usize, mode: OutputMode) -> Vec<u8> {
+        let mut stages: Vec<Stage> = rules.iter().map(Stage::new).collect();
+        let mut out = Vec::new();
+        for piece in text.chunks(size) {
+            out.extend_from_slice(&feed(&mut stages, piece, false, mode));
+        }
+        out.extend_from_slice(&feed(&mut stages, &[], true, mode));
+        out
+    }
+
+    /// Apply `rules` to all of `text` at once.
+    fn all_at_once(rules: &[Rule], text: &[u8], mode: OutputMode) -> Vec<u8> {
+        let mut text = text.to_vec();
+        for rule in rules.iter() {
+            text = match mode {
+                OutputMode::Replace => rule.replace(&text).into_owned(),
+                OutputMode::Extract => {
+                    let mut out = Vec::new();
+                    rule.extract(&text, &mut out);
+                    out
+                }
+            };
+        }
+        text
+    }
+
+    fn sample() -> Vec<u8> {
+        let line = "a cat, abab concat\nxxxxx catcat ab\n\nend of the ab line\n";
+        line.repeat(40).into_bytes()
+    }
+
+    fn check(rules: &[Rule]) {
+        let text = sample();
+        for mode in [OutputMode::Replace, OutputMode::Extract] {
+            let expected = all_at_once(rules, &text, mode);
+            for size in [1, 2, 3, 7, 64, text.len()] {
+                assert_eq!(
+                    String::from_utf8_lossy(&stream(rules, &text, size, mode)),
+                    String::from_utf8_lossy(&expected),
+                    "{:?}, pieces of {}",
+                    mode,
+                    size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn streams() {
+        assert!(rule("ab", "X", usize::MAX, false).max_len.is_some());
+        check(&[rule("ab", "X", usize::MAX, false)]);
+        check(&[rule(r"\bcat\b", "dog", usize::MAX, false)]);
+        check(&[rule("x{2,3}", "[$0]", usize::MAX, false)]);
+        check(&[rule("b?", "-", usize::MAX, false)]);
+        check(&[rule("^a|b$", "#", usize::MAX, true)]);
+        check(&[rule(r"\n\n", "\n", usize::MAX, false)]);
+    }
+
+    #[test]
+    fn limited() {
+        check(&[rule("ab", "X", 3, false)]);
+        check(&[rule("cat", "dog", 1, false)]);
+    }
+
+    #[test]
+    fn chained() {
+        check(&[
+            rule("cat", "ab", usize::MAX, false),
+            rule("ab", "<$0>", usize::MAX, false),
+            rule(r"<ab>\n", ";", 5, false),
+        ]);
+    }
+
+    #[test]
+    fn unbounded() {
+        let wide = rule("a.*b", "_", usize::MAX, false);
+        assert!(wide.max_len.is_none());
+        check(&[wide, rule("cat", "dog", usize::MAX, false)]);
+    }
+}
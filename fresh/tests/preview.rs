@@ -0,0 +1,62 @@
+/*!
+`--diff` and `--check` shouldn't report changes that wouldn't be made.
+*/
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+/// Write `text` to a new file for the test `name`.
+fn scratch(name: &str, text: &[u8]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fresh-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn fresh(args: &[&str], path: &PathBuf) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fresh"))
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+static CRLF: &[u8] = b"one\r\ntwo\r\nthree\r\n";
+
+#[test]
+fn check_crlf_unchanged() {
+    let path = scratch("check_crlf_unchanged.txt", CRLF);
+    let out = fresh(&["--check", "zzz", "y"], &path);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn check_crlf_changed() {
+    let path = scratch("check_crlf_changed.txt", CRLF);
+    let out = fresh(&["--check", "two", "2"], &path);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(out.stdout, format!("{}\n", path.display()).into_bytes());
+}
+
+#[test]
+fn diff_crlf_unchanged() {
+    let path = scratch("diff_crlf_unchanged.txt", CRLF);
+    let out = fresh(&["--diff", "zzz", "y"], &path);
+    assert_eq!(out.status.code(), Some(0));
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn diff_crlf_changed() {
+    let path = scratch("diff_crlf_changed.txt", CRLF);
+    let out = fresh(&["--diff", "-U", "0", "two", "2"], &path);
+    let name = path.display();
+    let expected = format!("--- {}\n+++ {}\n@@ -2 +2 @@\n-two\r\n+2\r\n", name, name);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected);
+    // Previewing never touches the file.
+    assert_eq!(fs::read(&path).unwrap(), CRLF);
+}